@@ -0,0 +1,303 @@
+//! Benchmark of the writes of the downloaded chunks in the local file.
+//!
+//! A local HTTP server serves a remote content from memory, on the loopback interface, and
+//! `download_chunks` downloads it with several workers into a local file, using:
+//! * a file shared behind a lock, each write being a seek followed by a write - as the chunks were
+//!   written before the positional writes,
+//! * positional writes, each received block being written at once (`--no-write-buffer`),
+//! * positional writes, gathered by the write buffer of each chunk writer (the default).
+//!
+//! For each writer, the benchmark prints the best wall time of several rounds, its throughput,
+//! and the number of system calls sent to the local file to write the chunks (a seek and a write
+//! count as two calls).
+//!
+//! Usage: `cargo bench --bench write -- [<size in MiB> [<workers> [<rounds>]]]`
+//! (default: 200 MiB, 8 workers, 3 rounds)
+
+extern crate libsnatch;
+
+use libsnatch::Bytes;
+use libsnatch::cargo_helper::CargoInfo;
+use libsnatch::download::{ConnectionSettings, download_chunks};
+use libsnatch::error::SnatchError;
+use libsnatch::logs;
+use libsnatch::mirrors::{Mirror, MirrorPool};
+use libsnatch::ratelimit::Throttle;
+use libsnatch::retry::RetryPolicy;
+use libsnatch::scheduler::CHUNKS_PER_WORKER;
+use libsnatch::sink::{ChunkSink, FileSink};
+use libsnatch::state::{DownloadState, StateTracker};
+use libsnatch::timeouts::Timeouts;
+use libsnatch::write::OutputFileWriter;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Enum for the writers which are compared
+#[derive(Clone, Copy)]
+enum Writer {
+    LockedSeek,
+    Positional,
+    BufferedPositional,
+}
+
+impl Writer {
+    fn name(&self) -> &str {
+        match *self {
+            Writer::LockedSeek => "locked seek+write",
+            Writer::Positional => "positional write",
+            Writer::BufferedPositional => "buffered positional write",
+        }
+    }
+}
+
+/// Structure that writes the chunks in a file shared behind a lock, each write being a seek
+/// followed by a write
+struct LockedFileSink {
+    file: Mutex<File>,
+    calls: Arc<AtomicUsize>,
+}
+
+impl ChunkSink for LockedFileSink {
+    fn write_at(&self, offset: Bytes, buf: &[u8]) -> Result<(), SnatchError> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(buf)?;
+        self.calls.fetch_add(2, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn read_at(&self, offset: Bytes, buf: &mut [u8]) -> Result<(), SnatchError> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)?;
+        Ok(())
+    }
+
+    fn set_len(&self, length: Bytes) -> Result<(), SnatchError> {
+        self.file.lock().unwrap().set_len(length)?;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), SnatchError> {
+        self.file.lock().unwrap().sync_all()?;
+        Ok(())
+    }
+}
+
+/// Structure that counts the positional writes sent to a file sink
+struct CountingSink {
+    sink: FileSink,
+    calls: Arc<AtomicUsize>,
+}
+
+impl ChunkSink for CountingSink {
+    fn write_at(&self, offset: Bytes, buf: &[u8]) -> Result<(), SnatchError> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.sink.write_at(offset, buf)
+    }
+
+    fn read_at(&self, offset: Bytes, buf: &mut [u8]) -> Result<(), SnatchError> {
+        self.sink.read_at(offset, buf)
+    }
+
+    fn set_len(&self, length: Bytes) -> Result<(), SnatchError> {
+        self.sink.set_len(length)
+    }
+
+    fn finish(&self) -> Result<(), SnatchError> {
+        self.sink.finish()
+    }
+}
+
+/// Function to get the numeric argument at the given position, or its default value
+fn get_argument(args: &[String], index: usize, default: usize) -> usize {
+    args.iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .nth(index)
+        .map(|arg| arg.parse().expect("the arguments must be numbers"))
+        .unwrap_or(default)
+}
+
+/// Function to get some bytes which cannot be compressed, so the local file is really written
+fn new_content(length: usize) -> Vec<u8> {
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..length)
+        .map(|_| {
+                 seed ^= seed << 13;
+                 seed ^= seed >> 7;
+                 seed ^= seed << 17;
+                 seed as u8
+             })
+        .collect()
+}
+
+/// Function to serve the content on the loopback interface, accepting a `Range` header, and to
+/// get the URL of the content
+fn serve(content: Arc<Vec<u8>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/content.bin", listener.local_addr().unwrap());
+    thread::spawn(move || for stream in listener.incoming() {
+                      let content = content.clone();
+                      thread::spawn(move || {
+                                        let _ = stream.and_then(|stream| respond(stream, &content));
+                                    });
+                  });
+    url
+}
+
+/// Function to send the response to one request, then to close the connection
+fn respond(mut stream: TcpStream, content: &[u8]) -> io::Result<()> {
+    let mut range = None;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        if line.to_lowercase().starts_with("range: bytes=") {
+            let bounds: Vec<usize> = line[13..]
+                .trim()
+                .split('-')
+                .filter_map(|bound| bound.parse().ok())
+                .collect();
+            if bounds.len() == 2 {
+                range = Some((bounds[0], bounds[1].min(content.len() - 1)));
+            }
+        }
+        line.clear();
+    }
+
+    let header = match range {
+        Some((start, end)) => {
+            format!("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+                    start,
+                    end,
+                    content.len())
+        }
+        None => String::from("HTTP/1.1 200 OK\r\n"),
+    };
+    let (start, end) = range.unwrap_or((0, content.len() - 1));
+    stream.write_all(format!("{}Accept-Ranges: bytes\r\nContent-Length: {}\r\n\
+                              Connection: close\r\n\r\n",
+                             header,
+                             end - start + 1)
+                             .as_bytes())?;
+    stream.write_all(&content[start..end + 1])
+}
+
+/// Function to download the content once with the given writer, and to get the wall time and the
+/// number of system calls sent to the local file
+fn run(writer: Writer, url: &str, content: &[u8], nb_workers: u64) -> (Duration, usize) {
+    let path = env::temp_dir().join("snatch_bench_write.bin");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let sink: Arc<dyn ChunkSink> = match writer {
+        Writer::LockedSeek => {
+            Arc::new(LockedFileSink {
+                         file: Mutex::new(file),
+                         calls: calls.clone(),
+                     })
+        }
+        Writer::Positional |
+        Writer::BufferedPositional => {
+            Arc::new(CountingSink {
+                         sink: FileSink::new(file),
+                         calls: calls.clone(),
+                     })
+        }
+    };
+    let out_file = match writer {
+        Writer::BufferedPositional => OutputFileWriter::with_sink(sink),
+        _ => OutputFileWriter::with_sink(sink).with_write_buffer(0),
+    };
+    out_file.set_len(content.len() as Bytes).unwrap();
+
+    let cargo_info = CargoInfo {
+        url: String::from(url),
+        redirects: vec![],
+        accept_partialcontent: true,
+        auth_header: None,
+        checksums: vec![],
+        content_length: Some(content.len() as Bytes),
+        etag: None,
+        last_modified: None,
+        file_name: None,
+    };
+    let state = DownloadState::new(url, &cargo_info, nb_workers * CHUNKS_PER_WORKER);
+    let mirrors = MirrorPool::new(vec![Mirror {
+                                           url: String::from(url),
+                                           auth_header: None,
+                                           etag: None,
+                                           last_modified: None,
+                                       }]);
+    let connection = ConnectionSettings {
+        retry_policy: RetryPolicy::no_retry(),
+        throttle: Throttle::default(),
+        timeouts: Timeouts::default(),
+    };
+
+    let start = Instant::now();
+    download_chunks(cargo_info,
+                    out_file.clone(),
+                    StateTracker::in_memory(state),
+                    &mirrors,
+                    nb_workers,
+                    None,
+                    &connection)
+            .unwrap();
+    out_file.finish().unwrap();
+    let elapsed = start.elapsed();
+
+    assert!(fs::read(&path).unwrap() == content, "the downloaded content is corrupt");
+    fs::remove_file(&path).unwrap();
+    (elapsed, calls.load(Ordering::Relaxed))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let length = get_argument(&args, 0, 200) * 1024 * 1024;
+    let nb_workers = get_argument(&args, 1, 8) as u64;
+    let rounds = get_argument(&args, 2, 3);
+
+    // The progress bars are drawn on the standard error
+    logs::use_stderr();
+    let content = Arc::new(new_content(length));
+    let url = serve(content.clone());
+
+    let writers = [Writer::LockedSeek,
+                   Writer::Positional,
+                   Writer::BufferedPositional];
+    let mut results = vec![];
+    for writer in &writers {
+        let mut best = None;
+        for _ in 0..rounds {
+            let (elapsed, calls) = run(*writer, &url, &content, nb_workers);
+            if best.is_none_or(|(best_elapsed, _)| elapsed < best_elapsed) {
+                best = Some((elapsed, calls));
+            }
+        }
+        results.push((writer.name(), best.unwrap()));
+    }
+
+    println!("{} MiB, {} workers, best of {} rounds",
+             length / (1024 * 1024),
+             nb_workers,
+             rounds);
+    for (name, (elapsed, calls)) in results {
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        println!("{:<26} {:>7.3} s {:>8.1} MiB/s {:>8} write calls",
+                 name,
+                 seconds,
+                 length as f64 / (1024.0 * 1024.0) / seconds,
+                 calls);
+    }
+}
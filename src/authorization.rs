@@ -1,34 +1,89 @@
+use error::SnatchError;
+use hyper::Url;
 use hyper::header::{Authorization, Basic, Headers, Scheme};
-use std::fmt::{Display, Formatter, Result};
+use hyper::method::Method;
+use md5::Md5;
+use rand::random;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use util::to_hex;
 
 /// Enum for the different types of authorization required by a remote document.
 #[derive(Clone)]
 pub enum AuthorizationType {
     Basic,
+    Bearer,
     Digest,
+    /// A raw `Authorization` header value, given by the user
+    Custom,
     Unknown,
 }
 
+/// Enum for the hash algorithms of the Digest Authorization (RFC 7616)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+/// Structure that contains the parameters of a Digest challenge, sent by the remote server in
+/// the `WWW-Authenticate` header
+#[derive(Clone, Debug, PartialEq)]
+pub struct DigestChallenge {
+    pub realm: String,
+    pub nonce: String,
+    pub opaque: Option<String>,
+    pub algorithm: DigestAlgorithm,
+    /// true if the remote server accepts the `auth` quality of protection - if not, the
+    /// legacy RFC 2069 response is computed
+    pub qop_auth: bool,
+    /// true if the previous nonce has expired, so the request can be sent again with the new one
+    pub stale: bool,
+}
+
+/// Structure that contains the parameters of a Bearer challenge, sent by the remote server in
+/// the `WWW-Authenticate` header (RFC 6750)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BearerChallenge {
+    pub realm: Option<String>,
+    pub scope: Option<String>,
+    pub service: Option<String>,
+}
+
 /// Trait to extend functionalities of the Headers type, from `hyper`
 pub trait GetAuthorizationType {
     /// Function to get the authorization type (if any) of a remote document.
     /// The returned type is `Option<AuthorizationType>`.
     fn get_authorization_type(&self) -> Option<AuthorizationType>;
+
+    /// Function to get the Digest challenge (if any) of a remote document.
+    /// This function returns an error if the challenge is not supported.
+    fn get_digest_challenge(&self) -> Option<Result<DigestChallenge, SnatchError>>;
+
+    /// Function to get the parameters of the Bearer challenge of a remote document.
+    /// The parameters are empty if there is no Bearer challenge.
+    fn get_bearer_challenge(&self) -> BearerChallenge;
 }
 
 impl GetAuthorizationType for Headers {
     /// Function to get the `WWW-Authenticate` container, from a given header.
     /// This function returns an Option that contains a `AuthorizationType` type.
     fn get_authorization_type(&self) -> Option<AuthorizationType> {
-        match self.get_raw("WWW-Authenticate") {
-            Some(raw) => {
-                let header_content = String::from_utf8(raw.get(0).unwrap().clone()).unwrap();
+        match self.get_raw("WWW-Authenticate")
+                  .and_then(|raw| raw.first())
+                  .and_then(|value| String::from_utf8(value.clone()).ok()) {
+            Some(header_content) => {
                 let mut header_parts = header_content.split(" ");
 
                 let auth_type = match header_parts.next() {
                     Some(part) => {
                         match part {
                             "Basic" => AuthorizationType::Basic,
+                            "Bearer" => AuthorizationType::Bearer,
                             "Digest" => AuthorizationType::Digest,
                             _ => AuthorizationType::Unknown,
                         }
@@ -40,23 +95,254 @@ impl GetAuthorizationType for Headers {
             None => None,
         }
     }
+
+    fn get_digest_challenge(&self) -> Option<Result<DigestChallenge, SnatchError>> {
+        self.get_raw("WWW-Authenticate")
+            .and_then(|raw| {
+                          raw.iter()
+                              .filter_map(|value| String::from_utf8(value.clone()).ok())
+                              .find(|value| value.starts_with("Digest "))
+                      })
+            .map(|header_content| DigestChallenge::parse(&header_content["Digest ".len()..]))
+    }
+
+    fn get_bearer_challenge(&self) -> BearerChallenge {
+        self.get_raw("WWW-Authenticate")
+            .and_then(|raw| {
+                          raw.iter()
+                              .filter_map(|value| String::from_utf8(value.clone()).ok())
+                              .find(|value| value.starts_with("Bearer"))
+                      })
+            .map(|header_content| BearerChallenge::parse(&header_content["Bearer".len()..]))
+            .unwrap_or_default()
+    }
 }
 
 impl Display for AuthorizationType {
-    fn fmt(&self, f: &mut Formatter) -> Result {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             &AuthorizationType::Basic => write!(f, "Basic"),
+            &AuthorizationType::Bearer => write!(f, "Bearer"),
             &AuthorizationType::Digest => write!(f, "Digest"),
+            &AuthorizationType::Custom => write!(f, "Custom"),
             _ => write!(f, "Unknown"),
         }
     }
 }
 
+impl DigestAlgorithm {
+    fn from_name(name: &str) -> Option<DigestAlgorithm> {
+        match name.to_uppercase().as_str() {
+            "MD5" => Some(DigestAlgorithm::Md5),
+            "MD5-SESS" => Some(DigestAlgorithm::Md5Sess),
+            "SHA-256" => Some(DigestAlgorithm::Sha256),
+            "SHA-256-SESS" => Some(DigestAlgorithm::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    fn is_session(&self) -> bool {
+        *self == DigestAlgorithm::Md5Sess || *self == DigestAlgorithm::Sha256Sess
+    }
+
+    /// Function to hash the given data, as a lowercase hexadecimal string
+    fn hash(&self, data: &str) -> String {
+        match *self {
+            DigestAlgorithm::Md5 |
+            DigestAlgorithm::Md5Sess => to_hex(&Md5::digest(data.as_bytes())),
+            DigestAlgorithm::Sha256 |
+            DigestAlgorithm::Sha256Sess => to_hex(&Sha256::digest(data.as_bytes())),
+        }
+    }
+}
+
+impl Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            DigestAlgorithm::Md5 => write!(f, "MD5"),
+            DigestAlgorithm::Md5Sess => write!(f, "MD5-sess"),
+            DigestAlgorithm::Sha256 => write!(f, "SHA-256"),
+            DigestAlgorithm::Sha256Sess => write!(f, "SHA-256-sess"),
+        }
+    }
+}
+
+impl DigestChallenge {
+    /// Function to parse the parameters of a Digest challenge (without the `Digest` scheme)
+    pub fn parse(params: &str) -> Result<DigestChallenge, SnatchError> {
+        let mut realm = None;
+        let mut nonce = None;
+        let mut opaque = None;
+        let mut algorithm = DigestAlgorithm::Md5;
+        let mut qop = None;
+        let mut stale = false;
+
+        for (name, value) in parse_auth_params(params) {
+            match name.to_lowercase().as_str() {
+                "realm" => realm = Some(value),
+                "nonce" => nonce = Some(value),
+                "opaque" => opaque = Some(value),
+                "algorithm" => {
+                    algorithm = DigestAlgorithm::from_name(&value).ok_or_else(|| {
+                        SnatchError::AuthUnsupported(format!("Digest {}", value))
+                    })?
+                }
+                "qop" => qop = Some(value),
+                "stale" => stale = value.eq_ignore_ascii_case("true"),
+                _ => {}
+            }
+        }
+
+        let qop_auth = match qop {
+            Some(qop) => {
+                if !qop.split(',').any(|qop| qop.trim() == "auth") {
+                    return Err(SnatchError::AuthUnsupported(format!("Digest qop={}", qop)));
+                }
+                true
+            }
+            None => false,
+        };
+
+        match (realm, nonce) {
+            (Some(realm), Some(nonce)) => {
+                Ok(DigestChallenge {
+                       realm,
+                       nonce,
+                       opaque,
+                       algorithm,
+                       qop_auth,
+                       stale,
+                   })
+            }
+            _ => Err(SnatchError::AuthUnsupported(String::from("Digest without realm or nonce"))),
+        }
+    }
+}
+
+impl BearerChallenge {
+    /// Function to parse the parameters of a Bearer challenge (without the `Bearer` scheme)
+    pub fn parse(params: &str) -> BearerChallenge {
+        let mut challenge = BearerChallenge::default();
+        for (name, value) in parse_auth_params(params) {
+            match name.to_lowercase().as_str() {
+                "realm" => challenge.realm = Some(value),
+                "scope" => challenge.scope = Some(value),
+                "service" => challenge.service = Some(value),
+                _ => {}
+            }
+        }
+        challenge
+    }
+}
+
+impl Display for BearerChallenge {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let params: Vec<String> = vec![("realm", &self.realm),
+                                       ("scope", &self.scope),
+                                       ("service", &self.service)]
+                .into_iter()
+                .filter_map(|(name, value)| {
+                                value.as_ref().map(|value| format!("{}=\"{}\"", name, value))
+                            })
+                .collect();
+        write!(f, "{}", params.join(", "))
+    }
+}
+
+/// Function to parse the comma separated `name=value` parameters of an authorization challenge.
+/// The values can be quoted strings, which may contain commas and escaped characters.
+pub fn parse_auth_params(params: &str) -> Vec<(String, String)> {
+    let mut parsed_params = vec![];
+    let mut chars = params.chars().peekable();
+
+    loop {
+        // Skip the separators before the name
+        while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ',') {
+            chars.next();
+        }
+        let name: String = chars
+            .by_ref()
+            .take_while(|c| *c != '=')
+            .collect::<String>()
+            .trim()
+            .to_owned();
+        if name.is_empty() {
+            return parsed_params;
+        }
+
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            // Skip anything until the next parameter
+            while chars.peek().is_some_and(|c| *c != ',') {
+                chars.next();
+            }
+        } else {
+            value = chars
+                .by_ref()
+                .take_while(|c| *c != ',')
+                .collect::<String>()
+                .trim()
+                .to_owned();
+        }
+        parsed_params.push((name, value));
+    }
+}
+
+/// Structure that contains the Digest challenge being answered, and the number of requests
+/// sent with its nonce
+struct DigestNonce {
+    challenge: DigestChallenge,
+    nonce_count: usize,
+}
+
+/// Structure that contains what is needed to answer to a Digest challenge.
+/// The nonce and its count are shared between the clones and the forks of a factory, so the
+/// count never goes back while the nonce is the same.
+#[derive(Clone)]
+struct DigestContext {
+    nonce: Arc<Mutex<DigestNonce>>,
+    cnonce: String,
+}
+
+impl DigestContext {
+    fn new(challenge: DigestChallenge) -> DigestContext {
+        DigestContext {
+            nonce: Arc::new(Mutex::new(DigestNonce {
+                                           challenge,
+                                           nonce_count: 0,
+                                       })),
+            cnonce: new_cnonce(),
+        }
+    }
+
+    /// Function to get a context for another connection, with its own client nonce
+    fn fork(&self) -> DigestContext {
+        DigestContext {
+            nonce: self.nonce.clone(),
+            cnonce: new_cnonce(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AuthorizationHeaderFactory {
     authorization_type: AuthorizationType,
     username: String,
     password: Option<String>,
+    /// The Bearer token, or the raw value of a custom `Authorization` header
+    token: Option<String>,
+    digest_context: Option<DigestContext>,
 }
 
 impl AuthorizationHeaderFactory {
@@ -68,22 +354,107 @@ impl AuthorizationHeaderFactory {
             authorization_type: authorization_type,
             username: username,
             password: password,
+            token: None,
+            digest_context: None,
+        }
+    }
+
+    /// Function to get a factory for the Bearer Authorization, using the given token
+    pub fn bearer(token: String) -> AuthorizationHeaderFactory {
+        AuthorizationHeaderFactory {
+            token: Some(token),
+            ..AuthorizationHeaderFactory::new(AuthorizationType::Bearer, String::new(), None)
+        }
+    }
+
+    /// Function to get a factory which always sends the given `Authorization` header value
+    pub fn custom(header_value: String) -> AuthorizationHeaderFactory {
+        AuthorizationHeaderFactory {
+            token: Some(header_value),
+            ..AuthorizationHeaderFactory::new(AuthorizationType::Custom, String::new(), None)
+        }
+    }
+
+    /// Function to set the Digest challenge to answer to, for the Digest Authorization
+    pub fn with_digest_challenge(mut self,
+                                 challenge: DigestChallenge)
+                                 -> AuthorizationHeaderFactory {
+        self.digest_context = Some(DigestContext::new(challenge));
+        self
+    }
+
+    /// Function to get a factory for another connection.
+    /// For the Digest Authorization, the new factory has its own client nonce, but shares the
+    /// nonce count, so the remote server never sees the same count twice.
+    pub fn fork(&self) -> AuthorizationHeaderFactory {
+        let mut factory = self.clone();
+        factory.digest_context = self.digest_context.as_ref().map(DigestContext::fork);
+        factory
+    }
+
+    /// Function to answer to the new Digest challenge of a `401 Unauthorized` response, if the
+    /// remote server tells that the nonce has expired (`stale=true`).
+    /// The new nonce is shared with the forks of this factory, and its count starts again.
+    /// This function returns true if the request can be sent again.
+    pub fn renew_stale_nonce(&self, headers: &Headers) -> bool {
+        let context = match self.digest_context {
+            Some(ref context) => context,
+            None => return false,
+        };
+        let challenge = match headers.get_digest_challenge() {
+            Some(Ok(challenge)) => challenge,
+            _ => return false,
+        };
+        if !challenge.stale {
+            return false;
         }
+        let mut nonce = context.nonce.lock().unwrap();
+        // Another connection may have renewed the nonce already
+        if nonce.challenge.nonce != challenge.nonce {
+            nonce.challenge = challenge;
+            nonce.nonce_count = 0;
+        }
+        true
     }
 
-    pub fn build_header(&self) -> Authorization<String> {
+    /// Function to build the `Authorization` header of a request, given its method and URL
+    pub fn build_header(&self,
+                        method: &Method,
+                        url: &str)
+                        -> Result<Authorization<String>, SnatchError> {
         match self.authorization_type {
-            AuthorizationType::Basic => Authorization(format!("Basic {}", self)),
-            _ => {
-                epanic!(&format!("{} Authorization is not supported!",
-                                 self.authorization_type))
+            AuthorizationType::Basic => Ok(Authorization(format!("Basic {}", self))),
+            AuthorizationType::Bearer => {
+                Ok(Authorization(format!("Bearer {}", self.token.as_ref().map_or("", |t| t))))
+            }
+            AuthorizationType::Custom => {
+                Ok(Authorization(self.token.clone().unwrap_or_default()))
             }
+            AuthorizationType::Digest => {
+                let context = match self.digest_context {
+                    Some(ref context) => context,
+                    None => {
+                        return Err(SnatchError::AuthUnsupported(String::from("Digest without \
+                                                                              challenge")))
+                    }
+                };
+                let mut nonce = context.nonce.lock().unwrap();
+                nonce.nonce_count += 1;
+                Ok(Authorization(digest_header(&nonce.challenge,
+                                               &self.username,
+                                               self.password.as_ref().map_or("", |p| p.as_str()),
+                                               method,
+                                               &request_uri(url),
+                                               &context.cnonce,
+                                               nonce.nonce_count)))
+            }
+            _ => Err(SnatchError::AuthUnsupported(self.authorization_type.to_string())),
         }
     }
 }
 
 impl Display for AuthorizationHeaderFactory {
-    fn fmt(&self, f: &mut Formatter) -> Result {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self.authorization_type {
             AuthorizationType::Basic => {
                 let basic_auth = Basic {
@@ -102,3 +473,199 @@ impl Display for AuthorizationHeaderFactory {
         }
     }
 }
+
+/// Function to get a new random client nonce, for the Digest Authorization
+fn new_cnonce() -> String {
+    to_hex(&random::<[u8; 16]>())
+}
+
+/// Function to get the request target of an URL (its path and query), as used by the Digest
+/// Authorization
+fn request_uri(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => {
+            match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_owned(),
+            }
+        }
+        Err(_) => url.to_owned(),
+    }
+}
+
+/// Function to compute the value of the `Authorization` header which answers to a Digest
+/// challenge, for the request number `nonce_count` with this nonce
+fn digest_header(challenge: &DigestChallenge,
+                 username: &str,
+                 password: &str,
+                 method: &Method,
+                 uri: &str,
+                 cnonce: &str,
+                 nonce_count: usize)
+                 -> String {
+    let algorithm = challenge.algorithm;
+    let nc = format!("{:08x}", nonce_count);
+
+    let mut ha1 = algorithm.hash(&format!("{}:{}:{}", username, challenge.realm, password));
+    if algorithm.is_session() {
+        ha1 = algorithm.hash(&format!("{}:{}:{}", ha1, challenge.nonce, cnonce));
+    }
+    let ha2 = algorithm.hash(&format!("{}:{}", method, uri));
+
+    let response = if challenge.qop_auth {
+        algorithm.hash(&format!("{}:{}:{}:{}:auth:{}", ha1, challenge.nonce, nc, cnonce, ha2))
+    } else {
+        algorithm.hash(&format!("{}:{}:{}", ha1, challenge.nonce, ha2))
+    };
+
+    let mut header = format!("Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", \
+                              algorithm={}, response=\"{}\"",
+                             username,
+                             challenge.realm,
+                             challenge.nonce,
+                             uri,
+                             algorithm,
+                             response);
+    if let Some(ref opaque) = challenge.opaque {
+        header.push_str(&format!(", opaque=\"{}\"", opaque));
+    }
+    if challenge.qop_auth {
+        header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+    }
+    header
+}
+
+#[cfg(test)]
+mod test_bearer {
+
+    use hyper::header::Headers;
+    use super::{BearerChallenge, GetAuthorizationType};
+
+    #[test]
+    fn bearer_challenge_should_be_parsed() {
+        let mut headers = Headers::new();
+        headers.set_raw("WWW-Authenticate",
+                        vec![b"Bearer realm=\"https://auth.example.com/token\",\
+                               service=\"registry.example.com\",scope=\"repository:a/b:pull\""
+                                     .to_vec()]);
+        assert_eq!(BearerChallenge {
+                       realm: Some(String::from("https://auth.example.com/token")),
+                       scope: Some(String::from("repository:a/b:pull")),
+                       service: Some(String::from("registry.example.com")),
+                   },
+                   headers.get_bearer_challenge());
+    }
+
+    #[test]
+    fn bearer_challenge_without_parameters_should_be_empty() {
+        let mut headers = Headers::new();
+        headers.set_raw("WWW-Authenticate", vec![b"Bearer".to_vec()]);
+        assert_eq!(BearerChallenge::default(), headers.get_bearer_challenge());
+    }
+
+}
+
+#[cfg(test)]
+mod test_digest {
+
+    use hyper::header::Headers;
+    use hyper::method::Method;
+    use super::{AuthorizationHeaderFactory, AuthorizationType, DigestAlgorithm, DigestChallenge,
+                digest_header, parse_auth_params, request_uri};
+
+    const RFC_7616_CHALLENGE: &str = "realm=\"http-auth@example.org\", qop=\"auth, \
+                                      auth-int\", algorithm=SHA-256, \
+                                      nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+                                      opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+
+    const RFC_7616_CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    #[test]
+    fn auth_params_should_handle_quoted_commas() {
+        assert_eq!(vec![(String::from("realm"), String::from("a, b")),
+                        (String::from("algorithm"), String::from("MD5"))],
+                   parse_auth_params("realm=\"a, b\", algorithm=MD5"));
+    }
+
+    #[test]
+    fn challenge_should_be_parsed() {
+        let challenge = DigestChallenge::parse(RFC_7616_CHALLENGE).unwrap();
+        assert_eq!("http-auth@example.org", challenge.realm);
+        assert_eq!(DigestAlgorithm::Sha256, challenge.algorithm);
+        assert!(challenge.qop_auth);
+    }
+
+    #[test]
+    fn challenge_with_unknown_algorithm_should_not_be_supported() {
+        assert!(DigestChallenge::parse("realm=\"r\", nonce=\"n\", algorithm=SHA-512-256").is_err());
+    }
+
+    #[test]
+    fn sha256_response_should_match_rfc_7616() {
+        let challenge = DigestChallenge::parse(RFC_7616_CHALLENGE).unwrap();
+        let header = digest_header(&challenge,
+                                   "Mufasa",
+                                   "Circle of Life",
+                                   &Method::Get,
+                                   "/dir/index.html",
+                                   RFC_7616_CNONCE,
+                                   1);
+        let response = "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1";
+        assert!(header.contains(&format!("response=\"{}\"", response)));
+        assert!(header.contains("nc=00000001"));
+    }
+
+    #[test]
+    fn md5_response_should_match_rfc_7616() {
+        let challenge = DigestChallenge {
+            algorithm: DigestAlgorithm::Md5,
+            ..DigestChallenge::parse(RFC_7616_CHALLENGE).unwrap()
+        };
+        let header = digest_header(&challenge,
+                                   "Mufasa",
+                                   "Circle of Life",
+                                   &Method::Get,
+                                   "/dir/index.html",
+                                   RFC_7616_CNONCE,
+                                   1);
+        assert!(header.contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""));
+    }
+
+    #[test]
+    fn stale_challenge_should_renew_the_nonce_of_every_fork() {
+        let challenge = DigestChallenge::parse("realm=\"r\", nonce=\"old\", qop=\"auth\"")
+            .unwrap();
+        let factory = AuthorizationHeaderFactory::new(AuthorizationType::Digest,
+                                                      String::from("user"),
+                                                      Some(String::from("password")))
+                .with_digest_challenge(challenge);
+        let fork = factory.fork();
+        let header = |factory: &AuthorizationHeaderFactory| {
+            factory
+                .build_header(&Method::Get, "http://localhost/file")
+                .unwrap()
+                .0
+        };
+        assert!(header(&factory).contains("nc=00000001"));
+        // The forks share the nonce count
+        assert!(header(&fork).contains("nc=00000002"));
+
+        let mut headers = Headers::new();
+        headers.set_raw("WWW-Authenticate",
+                        vec![b"Digest realm=\"r\", nonce=\"new\", qop=\"auth\"".to_vec()]);
+        assert!(!fork.renew_stale_nonce(&headers));
+        headers.set_raw("WWW-Authenticate",
+                        vec![b"Digest realm=\"r\", nonce=\"new\", qop=\"auth\", stale=TRUE"
+                                 .to_vec()]);
+        assert!(fork.renew_stale_nonce(&headers));
+        let header = header(&factory);
+        assert!(header.contains("nonce=\"new\""));
+        assert!(header.contains("nc=00000001"));
+    }
+
+    #[test]
+    fn request_uri_should_keep_the_query() {
+        assert_eq!("/dir/file.zip?a=b", request_uri("http://localhost:8080/dir/file.zip?a=b"));
+    }
+
+}
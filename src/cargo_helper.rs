@@ -1,24 +1,112 @@
 use authorization::{AuthorizationHeaderFactory, AuthorizationType, GetAuthorizationType};
 use Bytes;
-use client::GetResponse;
-use contentlength::GetContentLength;
+use checksum::Checksum;
+use client::{GetResponse, new_client};
+use contentlength::{ContentRangeBytes, GetContentLength};
+use credentials::CredentialProvider;
+use error::SnatchError;
+use filename::content_disposition_file_name;
 use http_version::ValidateHttpVersion;
 use hyper::header::{ByteRangeSpec, Headers, Range};
 use hyper::client::Client;
+use hyper::client::response::Response;
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use integrity::GetIntegrityChecksums;
+use redirect::RedirectPolicy;
 use response::CheckResponseStatus;
 use std::result::Result;
-use util::prompt_user;
+use timeouts::Timeouts;
 
 pub struct CargoInfo {
+    /// The final URL of the remote content, once every redirection has been followed
+    pub url: String,
+    /// The URLs which have been redirected to the final URL, in order
+    pub redirects: Vec<String>,
     pub accept_partialcontent: bool,
     pub auth_header: Option<AuthorizationHeaderFactory>,
-    pub content_length: Bytes,
+    pub checksums: Vec<Checksum>,
+    /// The length of the remote content, if the remote server sends it
+    pub content_length: Option<Bytes>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// The name of the file given by the remote server, in its `Content-Disposition` header
+    pub file_name: Option<String>,
 }
 
-pub fn get_cargo_info(url: &str) -> Result<CargoInfo, String> {
-    let hyper_client = Client::new();
+/// Function to get the raw value of a header, as a `String`
+pub fn get_raw_header(headers: &Headers, name: &str) -> Option<String> {
+    headers
+        .get_raw(name)
+        .and_then(|raw| raw.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+}
+
+/// Function to send the probe request of a remote content: a GET request for its first byte
+/// only, so the remote server tells at once if it accepts PartialContent status, and the length
+/// of the remote content
+fn send_probe(hyper_client: &Client,
+              url: &str,
+              auth_header: &Option<AuthorizationHeaderFactory>)
+              -> Result<Response, SnatchError> {
+    let mut headers = Headers::new();
+    headers.set(Range::Bytes(vec![ByteRangeSpec::FromTo(0, 0)]));
+    if let Some(ref header_factory) = *auth_header {
+        headers.set(header_factory.build_header(&Method::Get, url)?);
+    }
+    Ok(hyper_client.get_http_response_using_headers(url, headers)?)
+}
+
+/// Function to get the length of a remote content using a HEAD request, when the probe request
+/// did not give it.
+/// Some servers do not accept HEAD requests: the length is then unknown.
+fn get_head_content_length(hyper_client: &Client,
+                           url: &str,
+                           auth_header: &Option<AuthorizationHeaderFactory>)
+                           -> Result<Option<Bytes>, SnatchError> {
+    let mut headers = Headers::new();
+    if let Some(ref header_factory) = *auth_header {
+        headers.set(header_factory.build_header(&Method::Head, url)?);
+    }
+    let client_response = hyper_client.get_head_response_using_headers(url, headers)?;
+    match client_response.status {
+        StatusCode::MethodNotAllowed |
+        StatusCode::NotImplemented => {
+            warning!("The remote server does not accept HEAD requests!");
+            Ok(None)
+        }
+        status if status.is_success() => Ok(client_response.headers.get_content_length()),
+        _ => Ok(None),
+    }
+}
 
-    let client_response = hyper_client.get_head_response(url).unwrap();
+/// Function to get the informations about a remote content, before downloading it: its length,
+/// its validators and checksums, the authorization needed to download it, and if the remote
+/// server accepts PartialContent status.
+/// They are given by a single GET request for the first byte of the remote content: a
+/// PartialContent response gives the length in its `Content-Range` header, while an OK
+/// response gives it in its `Content-Length` header. A HEAD request is sent only if the length is
+/// still unknown.
+/// If an authorization is given, it is sent with every request. Else, if the remote content is
+/// protected, the credentials are asked to the given provider.
+/// The redirections are followed as described by the given policy, so the final URL is resolved
+/// once, and the requests give up after the given timeouts.
+pub fn get_cargo_info(url: &str,
+                      credential_provider: &dyn CredentialProvider,
+                      auth_header: Option<AuthorizationHeaderFactory>,
+                      timeouts: &Timeouts,
+                      redirect_policy: &RedirectPolicy)
+                      -> Result<CargoInfo, SnatchError> {
+    let hyper_client = new_client(timeouts);
+    let probe = |url: &str, auth_header: &Option<AuthorizationHeaderFactory>| {
+        send_probe(&hyper_client, url, auth_header)
+    };
+
+    let redirected = redirect_policy.follow(url, auth_header, &probe)?;
+    let client_response = redirected.response;
+    let mut url = redirected.url;
+    let mut redirects = redirected.redirects;
+    let auth_header = redirected.auth_header;
 
     info!("Waiting a response from the remote server... ");
 
@@ -28,83 +116,123 @@ pub fn get_cargo_info(url: &str) -> Result<CargoInfo, String> {
         ok!("");
     }
 
+    let preset_auth = auth_header.is_some();
     let auth_type = client_response.headers.get_authorization_type();
     let auth_header_factory = match auth_type {
+        // The given authorization has already been sent
+        _ if preset_auth => auth_header,
         Some(a_type) => {
             match a_type {
                 AuthorizationType::Basic => {
-                    warning!("The remote content is protected by Basic Auth.");
-                    warning!("Please to enter below your credential informations.");
-                    let username = prompt_user("Username:");
-                    let password = prompt_user("Password:");
+                    let credentials = credential_provider
+                        .get_credentials(&url, &a_type)?
+                        .ok_or(SnatchError::MissingCredentials)?;
                     Some(AuthorizationHeaderFactory::new(AuthorizationType::Basic,
-                                                         username,
-                                                         Some(password)))
+                                                         credentials.username,
+                                                         credentials.password))
+                }
+                AuthorizationType::Digest => {
+                    let challenge = client_response
+                        .headers
+                        .get_digest_challenge()
+                        .unwrap_or_else(|| {
+                                            Err(SnatchError::AuthUnsupported(a_type.to_string()))
+                                        })?;
+                    let credentials = credential_provider
+                        .get_credentials(&url, &a_type)?
+                        .ok_or(SnatchError::MissingCredentials)?;
+                    Some(AuthorizationHeaderFactory::new(AuthorizationType::Digest,
+                                                         credentials.username,
+                                                         credentials.password)
+                                 .with_digest_challenge(challenge))
+                }
+                AuthorizationType::Bearer => {
+                    let challenge = client_response.headers.get_bearer_challenge();
+                    let token = credential_provider
+                        .get_token(&url, &challenge)?
+                        .ok_or(SnatchError::MissingCredentials)?;
+                    Some(AuthorizationHeaderFactory::bearer(token))
                 }
                 _ => {
-                    return Err(format!("The remote content is protected by {} \
-                                                 Authorization, which is not supported!\nYou \
-                                                 can create a new issue to report this problem \
-                                                 in https://github.\
-                                                 com/derniercri/snatch/issues/new",
-                                       a_type));
+                    return Err(SnatchError::AuthUnsupported(a_type.to_string()));
                 }
             }
         }
         None => None,
     };
 
-    let client_response = match auth_header_factory.clone() {
-        Some(header_factory) => {
-            let mut headers = Headers::new();
-            headers.set(header_factory.build_header());
-            hyper_client
-                .get_head_response_using_headers(&url, headers)
-                .unwrap()
+    let (client_response, auth_header_factory) = match auth_header_factory {
+        // Send again the request to the final URL, with the authorization which was not sent the
+        // first time - the remote content may be redirected again once authorized
+        Some(_) if !preset_auth => {
+            let redirected = redirect_policy.follow(&url, auth_header_factory, &probe)?;
+            redirects.extend(redirected.redirects);
+            url = redirected.url;
+            (redirected.response, redirected.auth_header)
         }
-        None => client_response,
+        _ => (client_response, auth_header_factory),
     };
 
-    let remote_content_length = match client_response.headers.get_content_length() {
-        Some(remote_content_length) => remote_content_length,
-        None => {
-            warning!("Cannot get the remote content length, using an \
-                                 HEADER request.");
-            warning!("Trying to send an HTTP request, to get the remote \
-                                 content length...");
+    info!("Checking the server's support for PartialContent headers...");
 
-            // Trying to force the server to send to us the remote content length
-            let mut custom_http_header = Headers::new();
-            // HTTP header to get all the remote content - if the response is OK, get the
-            // ContentLength information sent back from the server
-            custom_http_header.set(Range::Bytes(vec![ByteRangeSpec::AllFrom(0)]));
-            // Get a response from the server, using the custom HTTP request
-            let client_response = hyper_client
-                .get_http_response_using_headers(&url, custom_http_header)
-                .unwrap();
-            // Try again to get the content length - if this one is unknown again, stop the program
-            match client_response.headers.get_content_length() {
-                Some(remote_content_length) => remote_content_length,
-                None => {
-                    return Err("Second attempt has failed.".to_string());
-                }
+    let content_range = client_response.headers.get_content_range();
+    let (accept_partialcontent, remote_content_length) = match client_response.status {
+        StatusCode::PartialContent => {
+            match content_range {
+                Some(ContentRangeBytes {
+                         range: Some((0, 0)),
+                         total_length,
+                     }) => (true, total_length),
+                _ => (false, content_range.and_then(|content_range| content_range.total_length)),
             }
         }
+        // The remote content is empty, so even its first byte cannot be sent
+        StatusCode::RangeNotSatisfiable => {
+            (false,
+             Some(content_range
+                      .and_then(|content_range| content_range.total_length)
+                      .unwrap_or(0)))
+        }
+        // The remote server ignores the range, and sends the whole remote content
+        status if status.is_success() => (false, client_response.headers.get_content_length()),
+        status => return Err(SnatchError::HttpStatus(status)),
     };
 
-    // Ask the first byte, just to know if the server accept PartialContent status
-    let mut header = Headers::new();
-    header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(0, 1)]));
-
-    let client_response = hyper_client
-        .get_head_response_using_headers(url, header)
-        .unwrap();
+    let remote_content_length = match remote_content_length {
+        Some(remote_content_length) => Some(remote_content_length),
+        None => {
+            warning!("Cannot get the remote content length, using a GET request.");
+            warning!("Trying to send a HEAD request, to get the remote content length...");
+            // If this one is unknown again, the remote content is streamed (e.g. using the
+            // chunked transfer encoding)
+            get_head_content_length(&hyper_client, &url, &auth_header_factory)?
+        }
+    };
 
-    info!("Checking the server's support for PartialContent headers...");
+    // Keep the validators of the remote content, to know later if it has changed
+    let etag = get_raw_header(&client_response.headers, "ETag");
+    let last_modified = get_raw_header(&client_response.headers, "Last-Modified");
+    let file_name = get_raw_header(&client_response.headers, "Content-Disposition")
+        .and_then(|content_disposition| content_disposition_file_name(&content_disposition));
+    // Keep the checksums sent by the remote server, to check the downloaded file - except the
+    // digest of the first byte only
+    let mut checksums = client_response.headers.get_representation_checksums();
+    if client_response.check_partialcontent_status() {
+        if let Some(content_md5) = client_response.headers.get_content_md5() {
+            checksums.retain(|checksum| *checksum != content_md5);
+        }
+    }
 
     Ok(CargoInfo {
-           accept_partialcontent: client_response.check_partialcontent_status(),
+           url,
+           redirects,
+           // A remote content of unknown length cannot be split in chunks
+           accept_partialcontent: accept_partialcontent && remote_content_length.is_some(),
            auth_header: auth_header_factory,
+           checksums,
            content_length: remote_content_length,
+           etag,
+           last_modified,
+           file_name,
        })
 }
@@ -0,0 +1,324 @@
+use blake3;
+use client::fetch_text;
+use error::SnatchError;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use util::{from_hex, to_hex};
+
+/// Constant to represent the length of the buffer used to read a local file, in order to hash it
+const HASH_BUFFER_BYTES: usize = 1024 * 64;
+
+/// Enum for the hash algorithms which can be used to check a downloaded file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    /// Function to get an algorithm from its name (e.g. `sha256`), whatever its case
+    pub fn from_name(name: &str) -> Option<ChecksumAlgorithm> {
+        match name.to_lowercase().replace('-', "").as_str() {
+            "md5" => Some(ChecksumAlgorithm::Md5),
+            "sha1" => Some(ChecksumAlgorithm::Sha1),
+            "sha256" => Some(ChecksumAlgorithm::Sha256),
+            "sha512" => Some(ChecksumAlgorithm::Sha512),
+            "blake3" | "b3" => Some(ChecksumAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Function to get the length of a digest of this algorithm, in bytes
+    pub fn digest_length(&self) -> usize {
+        match *self {
+            ChecksumAlgorithm::Md5 => 16,
+            ChecksumAlgorithm::Sha1 => 20,
+            ChecksumAlgorithm::Sha256 |
+            ChecksumAlgorithm::Blake3 => 32,
+            ChecksumAlgorithm::Sha512 => 64,
+        }
+    }
+}
+
+impl Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            ChecksumAlgorithm::Md5 => write!(f, "md5"),
+            ChecksumAlgorithm::Sha1 => write!(f, "sha1"),
+            ChecksumAlgorithm::Sha256 => write!(f, "sha256"),
+            ChecksumAlgorithm::Sha512 => write!(f, "sha512"),
+            ChecksumAlgorithm::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+/// Structure that hashes some bytes incrementally, using one of the checksum algorithms
+pub enum Hasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Hasher {
+        match algorithm {
+            ChecksumAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            ChecksumAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            ChecksumAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match *self {
+            Hasher::Md5(ref mut hasher) => hasher.update(data),
+            Hasher::Sha1(ref mut hasher) => hasher.update(data),
+            Hasher::Sha256(ref mut hasher) => hasher.update(data),
+            Hasher::Sha512(ref mut hasher) => hasher.update(data),
+            Hasher::Blake3(ref mut hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// Function to get the digest of all the bytes given to the hasher
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Structure that contains an expected digest of a remote content, and the algorithm to compute
+/// it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub digest: Vec<u8>,
+}
+
+impl Checksum {
+    /// Function to get a checksum from its hexadecimal digest, or None if this digest is not
+    /// valid for the given algorithm
+    pub fn from_hex(algorithm: ChecksumAlgorithm, hex_digest: &str) -> Option<Checksum> {
+        from_hex(hex_digest)
+            .filter(|digest| digest.len() == algorithm.digest_length())
+            .map(|digest| {
+                     Checksum {
+                         algorithm,
+                         digest,
+                     }
+                 })
+    }
+
+    /// Function to compute the checksum of the given bytes
+    pub fn compute(algorithm: ChecksumAlgorithm, data: &[u8]) -> Checksum {
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(data);
+        Checksum {
+            algorithm,
+            digest: hasher.finalize(),
+        }
+    }
+}
+
+impl Display for Checksum {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}:{}", self.algorithm, to_hex(&self.digest))
+    }
+}
+
+/// Function to check that a local file has all the given checksums.
+/// The file is read only once, whatever the number of checksums.
+pub fn verify_file(path: &Path, checksums: &[Checksum]) -> Result<(), SnatchError> {
+    if checksums.is_empty() {
+        return Ok(());
+    }
+
+    let mut file = File::open(path)?;
+    let mut verifier = ChecksumVerifier::new(checksums);
+    let mut buffer = vec![0u8; HASH_BUFFER_BYTES];
+    loop {
+        let bcount = file.read(&mut buffer)?;
+        if bcount == 0 {
+            break;
+        }
+        verifier.update(&buffer[..bcount]);
+    }
+    verifier.verify()
+}
+
+/// Structure that computes the checksums of a content received in several parts, to check it
+/// against the expected checksums
+pub struct ChecksumVerifier {
+    expected: Vec<Checksum>,
+    hashers: Vec<Hasher>,
+}
+
+impl ChecksumVerifier {
+    pub fn new(checksums: &[Checksum]) -> ChecksumVerifier {
+        ChecksumVerifier {
+            expected: checksums.to_vec(),
+            hashers: checksums
+                .iter()
+                .map(|checksum| Hasher::new(checksum.algorithm))
+                .collect(),
+        }
+    }
+
+    /// Function to add the next part of the content
+    pub fn update(&mut self, data: &[u8]) {
+        for hasher in &mut self.hashers {
+            hasher.update(data);
+        }
+    }
+
+    /// Function to check the whole content against each expected checksum
+    pub fn verify(self) -> Result<(), SnatchError> {
+        for (expected, hasher) in self.expected.into_iter().zip(self.hashers) {
+            let computed = Checksum {
+                algorithm: expected.algorithm,
+                digest: hasher.finalize(),
+            };
+            if computed != expected {
+                return Err(SnatchError::ChecksumMismatch {
+                               expected,
+                               computed,
+                           });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Function to get the expected checksum of a local file, from a `--checksum` argument.
+/// This argument is either `algorithm:digest`, or `algorithm:source` where the source is the path
+/// or the URL of a `SHA256SUMS`-style file - in this case, the digest of the entry matching the
+/// given file name is used.
+pub fn resolve_checksum(argument: &str, file_name: &str) -> Result<Checksum, SnatchError> {
+    let mut argument_parts = argument.splitn(2, ':');
+    let algorithm_name = argument_parts.next().unwrap_or("");
+    let value = argument_parts.next().unwrap_or("");
+    let algorithm = ChecksumAlgorithm::from_name(algorithm_name)
+        .ok_or_else(|| SnatchError::InvalidChecksum(String::from(argument)))?;
+
+    if let Some(checksum) = Checksum::from_hex(algorithm, value) {
+        return Ok(checksum);
+    }
+
+    let content = if value.starts_with("http://") || value.starts_with("https://") {
+        fetch_text(value)?
+    } else {
+        let mut content = String::new();
+        File::open(value)?.read_to_string(&mut content)?;
+        content
+    };
+
+    find_checksum(&content, file_name)
+        .and_then(|hex_digest| Checksum::from_hex(algorithm, &hex_digest))
+        .ok_or_else(|| SnatchError::InvalidChecksum(format!("{} in {}", file_name, value)))
+}
+
+/// Function to get the hexadecimal digest of the given file, from the content of a
+/// `SHA256SUMS`-style file.
+/// Both the GNU format (`digest  name`, or `digest *name` for binary files) and the BSD format
+/// (`SHA256 (name) = digest`) are accepted. If no entry has exactly this name, an entry in a
+/// subdirectory with the same file name is used.
+fn find_checksum(content: &str, file_name: &str) -> Option<String> {
+    let entries: Vec<(&str, &str)> = content
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.starts_with('#') {
+                return None;
+            }
+            // BSD format
+            if let (Some(open), Some(close)) = (line.find(" ("), line.rfind(") = ")) {
+                if open < close {
+                    return Some((&line[open + 2..close], line[close + 4..].trim()));
+                }
+            }
+            // GNU format
+            let mut line_parts = line.splitn(2, char::is_whitespace);
+            match (line_parts.next(), line_parts.next()) {
+                (Some(digest), Some(name)) => {
+                    let name = name.trim_start();
+                    Some((name.trim_start_matches('*'), digest))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+
+    entries
+        .iter()
+        .find(|&&(name, _)| name == file_name || name.trim_start_matches("./") == file_name)
+        .or_else(|| {
+                     entries
+                         .iter()
+                         .find(|&&(name, _)| name.rsplit('/').next() == Some(file_name))
+                 })
+        .map(|&(_, digest)| String::from(digest))
+}
+
+#[cfg(test)]
+mod test_checksum {
+
+    use super::{Checksum, ChecksumAlgorithm, find_checksum};
+    use util::to_hex;
+
+    #[test]
+    fn digests_should_match_known_vectors() {
+        let digest = |algorithm| to_hex(&Checksum::compute(algorithm, b"abc").digest);
+        assert_eq!("900150983cd24fb0d6963f7d28e17f72",
+                   digest(ChecksumAlgorithm::Md5));
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d",
+                   digest(ChecksumAlgorithm::Sha1));
+        assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                   digest(ChecksumAlgorithm::Sha256));
+        assert_eq!("6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+                   digest(ChecksumAlgorithm::Blake3));
+        assert_eq!(128, digest(ChecksumAlgorithm::Sha512).len());
+    }
+
+    #[test]
+    fn checksum_should_have_the_length_of_its_algorithm() {
+        assert!(Checksum::from_hex(ChecksumAlgorithm::Md5, "900150983CD24FB0D6963F7D28E17F72")
+                    .is_some());
+        assert!(Checksum::from_hex(ChecksumAlgorithm::Sha256, "900150983cd24fb0d6963f7d28e17f72")
+                    .is_none());
+        assert!(Checksum::from_hex(ChecksumAlgorithm::Md5, "SHA256SUMS").is_none());
+    }
+
+    #[test]
+    fn sums_file_should_give_the_entry_of_the_file() {
+        let content = "# comment\n\
+                       aaaa  snatch-0.1.3.tar.gz\n\
+                       bbbb *snatch-0.1.3.zip\n\
+                       SHA256 (snatch.exe) = cccc\n\
+                       dddd  ./dist/snatch.deb\n";
+        assert_eq!(Some(String::from("aaaa")),
+                   find_checksum(content, "snatch-0.1.3.tar.gz"));
+        assert_eq!(Some(String::from("bbbb")),
+                   find_checksum(content, "snatch-0.1.3.zip"));
+        assert_eq!(Some(String::from("cccc")), find_checksum(content, "snatch.exe"));
+        assert_eq!(Some(String::from("dddd")), find_checksum(content, "snatch.deb"));
+        assert_eq!(None, find_checksum(content, "snatch.rpm"));
+    }
+
+}
@@ -1,8 +1,17 @@
-use hyper::client::Client;
+use error::SnatchError;
+use hyper::client::{self, Client, Pool};
 use hyper::client::response::Response;
 use hyper::error::Error;
 use hyper::header::Headers;
 use hyper::method::Method;
+#[cfg(feature = "ssl")]
+use hyper::net::{HttpsConnector, OpensslClient};
+use std::io;
+use std::io::Read;
+use std::net::{TcpStream, ToSocketAddrs};
+use redirect::RedirectPolicy;
+use std::time::Duration;
+use timeouts::Timeouts;
 
 /// Trait that represents some methods to send a specific request
 pub trait GetResponse {
@@ -50,3 +59,56 @@ impl GetResponse for Client {
         self.request(Method::Get, url).headers(custom_header).send()
     }
 }
+
+/// Function to open a TCP connection to a remote server, trying each of its addresses in turn,
+/// and giving up on an address after the given timeout
+fn connect_tcp(host: &str, port: u16, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return TcpStream::connect((host, port)),
+    };
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput,
+                                        "the remote server has no address");
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+/// Function to create a hyper client which gives up on the connections, the requests and the
+/// responses of a remote server after the given timeouts.
+/// The connections are kept alive between the requests, as with the default hyper client.
+/// The redirections are not followed by this client, but by a `RedirectPolicy`.
+pub fn new_client(timeouts: &Timeouts) -> Client {
+    let connect_timeout = timeouts.connect;
+    let connector = move |host: &str, port: u16, _scheme: &str| {
+        connect_tcp(host, port, connect_timeout)
+    };
+    #[cfg(feature = "ssl")]
+    let connector = HttpsConnector::with_connector(OpensslClient::default(), connector);
+
+    let mut client = Client::with_connector(Pool::with_connector(Default::default(), connector));
+    client.set_read_timeout(timeouts.idle_timeout());
+    client.set_write_timeout(timeouts.read);
+    client.set_redirect_policy(client::RedirectPolicy::FollowNone);
+    client
+}
+
+/// Function to get the content of a small remote text file
+pub fn fetch_text(url: &str) -> Result<String, SnatchError> {
+    let hyper_client = new_client(&Timeouts::default());
+    let mut client_response = RedirectPolicy::default()
+        .follow(url,
+                None,
+                |url, _| Ok(hyper_client.get_http_response(url)?))?
+        .response;
+    if !client_response.status.is_success() {
+        return Err(SnatchError::HttpStatus(client_response.status));
+    }
+    let mut content = String::new();
+    client_response.read_to_string(&mut content)?;
+    Ok(content)
+}
@@ -1,22 +1,95 @@
-use hyper::header::{ContentLength, Headers};
+use hyper::header::{ContentLength, ContentRange, ContentRangeSpec, Headers};
 use std::ops::Deref;
 
 use Bytes;
 
+/// Structure that contains the `Content-Range` header of a response, for a range of bytes
+#[derive(Debug, PartialEq)]
+pub struct ContentRangeBytes {
+    /// The first and the last byte sent, or None if the requested range cannot be satisfied
+    pub range: Option<(Bytes, Bytes)>,
+    /// The length of the whole remote content, or None if the remote server does not know it
+    pub total_length: Option<Bytes>,
+}
+
 /// Trait to extend functionalities of the Headers type, from `hyper`
 pub trait GetContentLength {
     /// Function to get the content length of a remote document.
     /// The returned type is `Option<Bytes>`.
     fn get_content_length(&self) -> Option<Bytes>;
+
+    /// Function to get the range of bytes sent in a PartialContent response, and the length of
+    /// the whole remote document, from the `content-range` header (e.g. `bytes 0-0/1234`).
+    fn get_content_range(&self) -> Option<ContentRangeBytes>;
 }
 
 impl GetContentLength for Headers {
     /// Function to get the `content-length` container, from a given header.
     /// This function returns an Option that contains a `Bytes` type.
     fn get_content_length(&self) -> Option<Bytes> {
-        if self.has::<ContentLength>() {
-            return Some(*self.get::<ContentLength>().unwrap().deref());
+        self.get::<ContentLength>().map(|content_length| *content_length.deref())
+    }
+
+    /// Function to get the `content-range` container, from a given header.
+    /// This function returns None if the header is missing, or if its unit is not `bytes`.
+    fn get_content_range(&self) -> Option<ContentRangeBytes> {
+        match self.get::<ContentRange>() {
+            Some(&ContentRange(ContentRangeSpec::Bytes {
+                                   range,
+                                   instance_length,
+                               })) => {
+                Some(ContentRangeBytes {
+                         range,
+                         total_length: instance_length,
+                     })
+            }
+            _ => None,
         }
-        None
     }
 }
+
+#[cfg(test)]
+mod test_content_range {
+
+    use hyper::header::Headers;
+    use super::{ContentRangeBytes, GetContentLength};
+
+    fn content_range(value: &str) -> Option<ContentRangeBytes> {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Range", vec![value.as_bytes().to_vec()]);
+        headers.get_content_range()
+    }
+
+    #[test]
+    fn content_range_should_give_the_range_and_the_total_length() {
+        assert_eq!(Some(ContentRangeBytes {
+                            range: Some((0, 0)),
+                            total_length: Some(1234),
+                        }),
+                   content_range("bytes 0-0/1234"));
+    }
+
+    #[test]
+    fn unknown_total_length_should_be_none() {
+        assert_eq!(Some(ContentRangeBytes {
+                            range: Some((0, 0)),
+                            total_length: None,
+                        }),
+                   content_range("bytes 0-0/*"));
+    }
+
+    #[test]
+    fn unsatisfiable_range_should_be_none() {
+        assert_eq!(Some(ContentRangeBytes {
+                            range: None,
+                            total_length: Some(0),
+                        }),
+                   content_range("bytes */0"));
+    }
+
+    #[test]
+    fn other_units_should_be_ignored() {
+        assert_eq!(None, content_range("pages 1-2/5"));
+    }
+
+}
@@ -0,0 +1,288 @@
+use authorization::{AuthorizationType, BearerChallenge};
+use error::SnatchError;
+use hyper::Url;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use util::{prompt_password, prompt_user};
+
+/// Default environment variable which contains the username
+pub const DEFAULT_USERNAME_VAR: &str = "SNATCH_USERNAME";
+
+/// Default environment variable which contains the password
+pub const DEFAULT_PASSWORD_VAR: &str = "SNATCH_PASSWORD";
+
+/// Default environment variable which contains the Bearer token
+pub const DEFAULT_TOKEN_VAR: &str = "SNATCH_BEARER_TOKEN";
+
+/// Structure that contains the credentials to access a protected remote content
+#[derive(Clone, Debug, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+impl Credentials {
+    pub fn new(username: &str, password: Option<&str>) -> Credentials {
+        Credentials {
+            username: String::from(username),
+            password: password.map(String::from),
+        }
+    }
+}
+
+/// Trait that represents a way to get the credentials needed by a protected remote content
+pub trait CredentialProvider {
+    /// Function to get the credentials to access the given URL, which is protected by the
+    /// given authorization type.
+    /// This function returns None if this provider does not know any credentials for this URL.
+    fn get_credentials(&self,
+                       url: &str,
+                       auth_type: &AuthorizationType)
+                       -> Result<Option<Credentials>, SnatchError>;
+
+    /// Function to get the Bearer token to access the given URL, given the parameters of the
+    /// Bearer challenge of the remote server.
+    /// This function returns None if this provider does not know any token for this URL.
+    fn get_token(&self,
+                 _url: &str,
+                 _challenge: &BearerChallenge)
+                 -> Result<Option<String>, SnatchError> {
+        Ok(None)
+    }
+}
+
+/// Provider which always gives the same credentials
+pub struct FixedCredentials(pub Credentials);
+
+impl CredentialProvider for FixedCredentials {
+    fn get_credentials(&self,
+                       _url: &str,
+                       _auth_type: &AuthorizationType)
+                       -> Result<Option<Credentials>, SnatchError> {
+        Ok(Some(self.0.clone()))
+    }
+}
+
+/// Provider which reads the credentials and the Bearer token from environment variables
+pub struct EnvCredentials {
+    pub username_var: String,
+    pub password_var: String,
+    pub token_var: String,
+}
+
+impl Default for EnvCredentials {
+    fn default() -> EnvCredentials {
+        EnvCredentials {
+            username_var: String::from(DEFAULT_USERNAME_VAR),
+            password_var: String::from(DEFAULT_PASSWORD_VAR),
+            token_var: String::from(DEFAULT_TOKEN_VAR),
+        }
+    }
+}
+
+impl CredentialProvider for EnvCredentials {
+    fn get_credentials(&self,
+                       _url: &str,
+                       _auth_type: &AuthorizationType)
+                       -> Result<Option<Credentials>, SnatchError> {
+        Ok(env::var(&self.username_var)
+               .ok()
+               .map(|username| {
+                        Credentials {
+                            username,
+                            password: env::var(&self.password_var).ok(),
+                        }
+                    }))
+    }
+
+    fn get_token(&self,
+                 _url: &str,
+                 _challenge: &BearerChallenge)
+                 -> Result<Option<String>, SnatchError> {
+        Ok(env::var(&self.token_var).ok())
+    }
+}
+
+/// Provider which reads the credentials of the remote host from a `.netrc` file
+pub struct NetrcCredentials {
+    pub path: PathBuf,
+}
+
+impl Default for NetrcCredentials {
+    /// Use the `.netrc` file of the home directory of the current user
+    fn default() -> NetrcCredentials {
+        let home = env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        NetrcCredentials { path: home.join(".netrc") }
+    }
+}
+
+impl CredentialProvider for NetrcCredentials {
+    fn get_credentials(&self,
+                       url: &str,
+                       _auth_type: &AuthorizationType)
+                       -> Result<Option<Credentials>, SnatchError> {
+        if !self.path.is_file() {
+            return Ok(None);
+        }
+        let host = match Url::parse(url).ok().and_then(|url| url.host_str().map(String::from)) {
+            Some(host) => host,
+            None => return Ok(None),
+        };
+
+        let mut content = String::new();
+        File::open(&self.path)?.read_to_string(&mut content)?;
+        Ok(parse_netrc(&content, &host))
+    }
+}
+
+/// Provider which asks the credentials to the user, without echoing the password
+pub struct PromptCredentials;
+
+impl CredentialProvider for PromptCredentials {
+    fn get_credentials(&self,
+                       _url: &str,
+                       auth_type: &AuthorizationType)
+                       -> Result<Option<Credentials>, SnatchError> {
+        warning!(&format!("The remote content is protected by {} Auth.", auth_type));
+        warning!("Please to enter below your credential informations.");
+        let username = prompt_user("Username:")?;
+        let password = prompt_password("Password:")?;
+        Ok(Some(Credentials {
+                    username,
+                    password: Some(password),
+                }))
+    }
+
+    fn get_token(&self,
+                 _url: &str,
+                 challenge: &BearerChallenge)
+                 -> Result<Option<String>, SnatchError> {
+        warning!(&format!("The remote content is protected by Bearer Auth ({}).", challenge));
+        Ok(Some(prompt_password("Token:")?))
+    }
+}
+
+/// Provider which asks each of its providers in turn, and gives the first credentials found
+pub struct CredentialChain(pub Vec<Box<dyn CredentialProvider>>);
+
+impl CredentialProvider for CredentialChain {
+    fn get_credentials(&self,
+                       url: &str,
+                       auth_type: &AuthorizationType)
+                       -> Result<Option<Credentials>, SnatchError> {
+        for provider in &self.0 {
+            if let Some(credentials) = provider.get_credentials(url, auth_type)? {
+                return Ok(Some(credentials));
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_token(&self,
+                 url: &str,
+                 challenge: &BearerChallenge)
+                 -> Result<Option<String>, SnatchError> {
+        for provider in &self.0 {
+            if let Some(token) = provider.get_token(url, challenge)? {
+                return Ok(Some(token));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Provider which never gives any credentials
+pub struct NoCredentials;
+
+impl CredentialProvider for NoCredentials {
+    fn get_credentials(&self,
+                       _url: &str,
+                       _auth_type: &AuthorizationType)
+                       -> Result<Option<Credentials>, SnatchError> {
+        Ok(None)
+    }
+}
+
+/// Function to get the credentials of the given host, from the content of a `.netrc` file.
+/// The `default` entry is used if there is no entry for this host.
+fn parse_netrc(content: &str, host: &str) -> Option<Credentials> {
+    let mut tokens = content.split_whitespace();
+    let mut host_credentials = None;
+    let mut default_credentials = None;
+
+    // The machine the current login and password belong to - None for the default entry
+    let mut current_machine: Option<Option<&str>> = None;
+    let mut current_login = None;
+    let mut current_password = None;
+
+    loop {
+        let token = tokens.next();
+        let is_new_entry = matches!(token,
+                                    Some("machine") | Some("default") | Some("macdef") | None);
+
+        if is_new_entry {
+            if let (Some(machine), Some(login)) = (current_machine, current_login) {
+                let credentials = Credentials::new(login, current_password);
+                match machine {
+                    Some(machine) if machine == host && host_credentials.is_none() => {
+                        host_credentials = Some(credentials)
+                    }
+                    None => default_credentials = Some(credentials),
+                    _ => {}
+                }
+            }
+            current_login = None;
+            current_password = None;
+        }
+
+        match token {
+            Some("machine") => current_machine = Some(tokens.next()),
+            Some("default") => current_machine = Some(None),
+            // The content of a macro is not an entry
+            Some("macdef") => current_machine = None,
+            Some("login") => current_login = tokens.next(),
+            Some("password") => current_password = tokens.next(),
+            Some(_) => {}
+            None => break,
+        }
+    }
+
+    host_credentials.or(default_credentials)
+}
+
+#[cfg(test)]
+mod test_netrc {
+
+    use super::{Credentials, parse_netrc};
+
+    const NETRC: &str = "machine example.com login alice password secret\n\
+                         machine other.com\n  login bob\n  password hunter2\n\
+                         default login anonymous password guest\n";
+
+    #[test]
+    fn netrc_should_give_the_credentials_of_the_host() {
+        assert_eq!(Some(Credentials::new("bob", Some("hunter2"))),
+                   parse_netrc(NETRC, "other.com"));
+        assert_eq!(Some(Credentials::new("alice", Some("secret"))),
+                   parse_netrc(NETRC, "example.com"));
+    }
+
+    #[test]
+    fn netrc_should_give_the_default_credentials_for_an_unknown_host() {
+        assert_eq!(Some(Credentials::new("anonymous", Some("guest"))),
+                   parse_netrc(NETRC, "unknown.com"));
+    }
+
+    #[test]
+    fn netrc_without_default_should_give_nothing_for_an_unknown_host() {
+        assert_eq!(None,
+                   parse_netrc("machine example.com login alice password secret",
+                               "unknown.com"));
+    }
+
+}
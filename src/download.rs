@@ -1,15 +1,33 @@
+use authorization::AuthorizationHeaderFactory;
 use cargo_helper::CargoInfo;
 use Bytes;
-use client::GetResponse;
+use checksum::{Checksum, ChecksumAlgorithm, Hasher};
+use client::{GetResponse, new_client};
+use contentlength::{ContentRangeBytes, GetContentLength};
+use error::SnatchError;
+use filesize::format_filesize;
 use hyper::client::Client;
+use hyper::client::response::Response;
 use hyper::error::Error;
 use hyper::header::{ByteRangeSpec, Headers, Range};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use integrity::GetIntegrityChecksums;
+use logs::console;
+use mirrors::{Mirror, MirrorPool};
+use pieces::PieceVerifier;
+use ratelimit::Throttle;
 use pbr::{MultiBar, Pipe, ProgressBar, Units};
 use response::CheckResponseStatus;
+use retry::RetryPolicy;
+use scheduler::ChunkScheduler;
+use state::{ChunkState, StateTracker};
 use std::cmp::min;
-use std::io::Read;
+use std::io;
+use std::io::{Read, Write};
 use std::thread;
 use std::time::{Instant, Duration};
+use timeouts::{MinSpeed, SpeedWatchdog, Timeouts};
 use write::{OutputFileWriter, OutputChunkWriter};
 
 /// Constant to represent the length of the buffer to download
@@ -20,8 +38,58 @@ const DOWNLOAD_BUFFER_BYTES: usize = 1024 * 64;
 /// for the CLI
 const PROGRESS_UPDATE_INTERVAL_MILLIS: u64 = 500;
 
+/// Function to get the error of a failed read from a connection.
+/// A read timeout is reported as such, instead of the `WouldBlock` error given by some platforms.
+fn read_error(error: io::Error) -> SnatchError {
+    let error = match error.kind() {
+        io::ErrorKind::WouldBlock => {
+            io::Error::new(io::ErrorKind::TimedOut, "the remote server stopped sending data")
+        }
+        _ => error,
+    };
+    SnatchError::Network(Error::Io(error))
+}
+
+/// Function to send a GET request to a mirror, with the given headers and its authorization.
+/// If the remote server tells that the Digest nonce has expired, the request is sent again at
+/// once with the new nonce.
+fn send_authorized_request(http_client: &Client,
+                           url: &str,
+                           http_header: Headers,
+                           auth_header_factory: &Option<AuthorizationHeaderFactory>)
+                           -> Result<Response, SnatchError> {
+    let mut renewed = false;
+    loop {
+        let mut request_header = http_header.clone();
+        if let Some(ref auth_header_factory) = *auth_header_factory {
+            request_header.set(auth_header_factory.build_header(&Method::Get, url)?);
+        }
+        let response = http_client.get_http_response_using_headers(url, request_header)?;
+        if response.status == StatusCode::Unauthorized && !renewed {
+            if let Some(ref auth_header_factory) = *auth_header_factory {
+                if auth_header_factory.renew_stale_nonce(&response.headers) {
+                    renewed = true;
+                    continue;
+                }
+            }
+        }
+        return Ok(response);
+    }
+}
+
+/// Structure that contains how the connections of a download behave
+#[derive(Clone)]
+pub struct ConnectionSettings {
+    /// The policy to retry the requests which failed
+    pub retry_policy: RetryPolicy,
+    /// The bandwidth limits, shared with the other downloads
+    pub throttle: Throttle,
+    /// The timeouts of the connections, and their minimum speed
+    pub timeouts: Timeouts,
+}
+
 /// Represents a range between two Bytes types
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 struct RangeBytes(Bytes, Bytes);
 
 macro_rules! initbar {
@@ -35,7 +103,7 @@ macro_rules! initbar {
         $mp.show_counter = false;
         $mp.show_time_left = true;
         $mp.set_units(Units::Bytes);
-        $mp.message(&format!("Chunk {} ", $index));
+        $mp.message(&format!("Worker {} ", $index));
     }
 }
 
@@ -51,7 +119,7 @@ fn get_chunk_length(chunk_index: u64,
 
     let b_range: Bytes = chunk_index * global_chunk_length;
 
-    if b_range >= (content_length - 1) {
+    if b_range >= content_length {
         return None;
     }
 
@@ -63,138 +131,577 @@ fn get_chunk_length(chunk_index: u64,
 }
 
 
-/// Function to get the HTTP header to send to the file server, for a chunk (specified by its index)
-fn get_header_from_index(chunk_index: u64,
-                         content_length: Bytes,
-                         global_chunk_length: Bytes)
-                         -> Option<(Headers, RangeBytes)> {
+/// Function to split a remote content into chunks of the same length (except for the last one),
+/// in order to download `nb_chunks` chunks.
+pub fn split_in_chunks(content_length: Bytes, nb_chunks: u64) -> Vec<ChunkState> {
+    if nb_chunks == 0 {
+        return vec![];
+    }
+
+    let global_chunk_length: Bytes = (content_length / nb_chunks) + 1;
+
+    (0..nb_chunks)
+        .filter_map(|chunk_index| {
+                        get_chunk_length(chunk_index, content_length, global_chunk_length)
+                    })
+        .map(|RangeBytes(start, end)| ChunkState::new(start, end))
+        .collect()
+}
+
+/// Function to check that the headers of a PartialContent response describe exactly the
+/// requested range of the remote content: the `Content-Range` header must contain the same
+/// range, and the same total length if it is known, and the `Content-Length` header must be the
+/// length of the range.
+fn check_content_range(headers: &Headers,
+                       requested_range: &RangeBytes,
+                       content_length: Bytes)
+                       -> Result<(), SnatchError> {
+    let RangeBytes(start, end) = *requested_range;
+    match headers.get_content_range() {
+        Some(ContentRangeBytes {
+                 range: Some(range),
+                 total_length,
+             }) if range == (start, end) &&
+                   total_length.is_none_or(|total_length| total_length == content_length) => {}
+        _ => return Err(SnatchError::RangeNotHonored),
+    }
+    match headers.get_content_length() {
+        Some(length) if length != end - start + 1 => Err(SnatchError::RangeNotHonored),
+        _ => Ok(()),
+    }
+}
 
-    get_chunk_length(chunk_index, content_length, global_chunk_length).map(|range| {
-        let mut header = Headers::new();
-        header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(range.0, range.1)]));
-        (header, RangeBytes(range.0, range.1 - range.0))
-    })
+/// Function to get the HTTP header to send to the file server, for the remaining part of a chunk
+fn get_header_from_chunk(chunk: &ChunkState) -> Headers {
+    let mut header = Headers::new();
+    header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(chunk.next_offset(), chunk.end)]));
+    header
 }
 
 
-/// Function to get from the server the content of a chunk.
-/// This function returns a Result type - Bytes if the content of the header is accessible, an Error type otherwise.
-fn download_a_chunk(http_client: &Client,
-                    http_header: Headers,
-                    mut chunk_writer: OutputChunkWriter,
-                    url: &str,
-                    mpb: &mut ProgressBar<Pipe>,
-                    monothreading: bool)
-                    -> Result<Bytes, Error> {
+/// Structure that contains everything a chunk thread needs to download its chunk
+struct ChunkDownload {
+    chunk_index: usize,
+    chunk: ChunkState,
+    chunk_writer: OutputChunkWriter,
+    mirrors: MirrorPool,
+    /// The authorization of each mirror, for the connections of this worker
+    auth_header_factories: Vec<Option<AuthorizationHeaderFactory>>,
+    check_partialcontent: bool,
+    content_length: Bytes,
+    state_tracker: StateTracker,
+    retry_policy: RetryPolicy,
+    /// The bandwidth limits of the connection of this worker
+    throttle: Throttle,
+    min_speed: Option<MinSpeed>,
+}
 
-    let mut body = http_client
-        .get_http_response_using_headers(url, http_header)
-        .unwrap();
-    if monothreading && !body.check_partialcontent_status() {
-        return Err(Error::Status);
+impl ChunkDownload {
+    /// Function to get from the mirrors the content of the chunk, retrying the remaining
+    /// part of the chunk on failure, as described by the retry policy.
+    /// Each attempt is sent to the mirror chosen by the mirror pool, so a failing mirror is
+    /// replaced by a healthy one.
+    /// This function returns a Result type - the number of downloaded Bytes if the chunk is
+    /// complete, an Error type otherwise.
+    fn download(&mut self,
+                http_client: &Client,
+                mpb: &mut ProgressBar<Pipe>)
+                -> Result<Bytes, SnatchError> {
+        let already_written = self.chunk.written;
+        let mut attempts = 0;
+
+        loop {
+            let mirror_index = self.mirrors.acquire();
+            let result = self.download_remaining(http_client, mirror_index, mpb);
+            self.mirrors.release(mirror_index);
+
+            match result {
+                // The mirror has been demoted while downloading: go on with another one
+                Ok(()) if !self.chunk.is_complete() => {}
+                Ok(()) => return Ok(self.chunk.written - already_written),
+                Err(error) => {
+                    attempts += 1;
+                    let demoted = self.mirrors.report_failure(mirror_index, &error);
+                    if !(error.is_retryable() || demoted) ||
+                       !self.retry_policy.should_retry(attempts) {
+                        return Err(error);
+                    }
+                    mpb.message(&format!("Chunk {} - retry {}/{} ",
+                                         self.chunk_index,
+                                         attempts,
+                                         self.retry_policy.max_attempts - 1));
+                    thread::sleep(self.retry_policy.delay(attempts));
+                }
+            }
+        }
     }
-    let mut bytes_buffer = [0; DOWNLOAD_BUFFER_BYTES];
-    let mut sum_bytes = 0;
 
-    let progress_update_interval = Duration::from_millis(PROGRESS_UPDATE_INTERVAL_MILLIS);
-    let mut last_progress_bytes = 0;
-    let mut last_progress_time = Instant::now() - progress_update_interval;
+    /// Function to send one request for the remaining part of the chunk to the given mirror, and
+    /// to write its content in the local file.
+    /// The chunk progress is kept even if an error occurs, so the next attempt starts from
+    /// the last written byte. If the mirror is demoted meanwhile, the request is stopped before
+    /// the end of the chunk.
+    /// The request is aborted if its throughput stays under the minimum speed.
+    fn download_remaining(&mut self,
+                          http_client: &Client,
+                          mirror_index: usize,
+                          mpb: &mut ProgressBar<Pipe>)
+                          -> Result<(), SnatchError> {
+        let mirror = self.mirrors.mirrors()[mirror_index].clone();
+        let url = &mirror.url;
+        let requested_range = RangeBytes(self.chunk.next_offset(), self.chunk.end);
+        let mut http_header = get_header_from_chunk(&self.chunk);
+        // Ask the range only if the remote content has not changed since the beginning of the
+        // download, so two versions of the remote content are never mixed up
+        if self.check_partialcontent {
+            if let Some(if_range) = mirror.if_range() {
+                http_header.set_raw("If-Range", vec![if_range.as_bytes().to_vec()]);
+            }
+        }
 
-    while let Ok(n) = body.read(&mut bytes_buffer) {
-        if n == 0 {
-            return Ok(sum_bytes);
+        let mut body = send_authorized_request(http_client,
+                                               url,
+                                               http_header,
+                                               &self.auth_header_factories[mirror_index])?;
+        if body.status == StatusCode::PreconditionFailed {
+            return Err(SnatchError::ContentChanged);
+        }
+        if !body.status.is_success() {
+            return Err(SnatchError::HttpStatus(body.status));
+        }
+        if body.check_partialcontent_status() {
+            check_content_range(&body.headers, &requested_range, self.content_length)?;
+        } else if self.check_partialcontent {
+            // The remote server sends the whole remote content, because it has changed or
+            // because the remote server ignores the range
+            if !mirror.has_same_content(&body.headers) {
+                return Err(SnatchError::ContentChanged);
+            }
+            return Err(SnatchError::RangeNotHonored);
+        } else if self.chunk.written > 0 {
+            // The whole remote content is sent again, from its first byte
+            self.chunk.written = 0;
+            self.state_tracker.update(self.chunk_index, self.chunk.written);
+            mpb.set(self.chunk.written);
         }
 
-        chunk_writer.write(sum_bytes, &bytes_buffer[0..n]);
+        // The remote server may send the MD5 digest of the requested range, to check it
+        let content_md5 = body.headers.get_content_md5();
+        let mut md5_hasher = content_md5.as_ref().map(|_| Hasher::new(ChecksumAlgorithm::Md5));
+        let requested_written = self.chunk.written;
+        let requested_end = self.chunk.end;
+        let mut bytes_buffer = [0; DOWNLOAD_BUFFER_BYTES];
+
+        let progress_update_interval = Duration::from_millis(PROGRESS_UPDATE_INTERVAL_MILLIS);
+        let mut last_progress_bytes = self.chunk.written;
+        let mut last_progress_time = Instant::now() - progress_update_interval;
+        // Bytes received since the last report to the mirror pool
+        let mut last_report_bytes = self.chunk.written;
+        let mut last_report_time = Instant::now();
+        let mut watchdog = SpeedWatchdog::new(self.min_speed);
+
+        let result = loop {
+            self.refresh_chunk_end(mpb);
+            if self.chunk.is_complete() || self.mirrors.is_demoted(mirror_index) {
+                break Ok(());
+            }
 
-        sum_bytes += n as u64;
+            let n = match body.read(&mut bytes_buffer) {
+                Ok(0) => {
+                    // The connection has been closed before the end of the chunk
+                    break Err(SnatchError::LengthMismatch {
+                                  expected: self.chunk.length(),
+                                  received: self.chunk.written,
+                              });
+                }
+                Ok(n) => n,
+                Err(error) => break Err(read_error(error)),
+            };
+
+            // Never write further than the end of the chunk
+            self.refresh_chunk_end(mpb);
+            let n = min(n as Bytes, self.chunk.remaining()) as usize;
+            if let Err(error) = self.chunk_writer.write(self.chunk.written, &bytes_buffer[0..n]) {
+                break Err(error);
+            }
+            if let Some(ref mut md5_hasher) = md5_hasher {
+                md5_hasher.update(&bytes_buffer[0..n]);
+            }
 
-        // Update the CLI
-        if Instant::now().duration_since(last_progress_time) > progress_update_interval {
-            last_progress_time = Instant::now();
-            let progress_bytes_delta = sum_bytes - last_progress_bytes;
-            last_progress_bytes = sum_bytes;
-            mpb.add(progress_bytes_delta);
+            self.chunk.written += n as u64;
+            // Only the bytes in the local file are saved in the state
+            self.state_tracker
+                .update(self.chunk_index,
+                        self.chunk.written - self.chunk_writer.pending() as Bytes);
+            let throttle_start = Instant::now();
+            self.throttle.consume(n as Bytes);
+            watchdog.pause(throttle_start.elapsed());
+            if let Err(error) = watchdog.check(n as Bytes) {
+                break Err(error);
+            }
+
+            // Update the CLI
+            if Instant::now().duration_since(last_progress_time) > progress_update_interval {
+                last_progress_time = Instant::now();
+                let progress_bytes_delta = self.chunk.written - last_progress_bytes;
+                last_progress_bytes = self.chunk.written;
+                mpb.add(progress_bytes_delta);
+
+                self.mirrors
+                    .report_transfer(mirror_index,
+                                     self.chunk.written - last_report_bytes,
+                                     last_report_time.elapsed());
+                last_report_bytes = self.chunk.written;
+                last_report_time = Instant::now();
+            }
+        };
+        // The buffered bytes are lost if they cannot be written, so they are received again
+        let pending = self.chunk_writer.pending() as Bytes;
+        let result = match self.chunk_writer.flush() {
+            Ok(()) => result,
+            Err(error) => {
+                self.chunk.written -= pending;
+                last_progress_bytes = min(last_progress_bytes, self.chunk.written);
+                mpb.set(last_progress_bytes);
+                result.and(Err(error))
+            }
+        };
+        self.state_tracker.update(self.chunk_index, self.chunk.written);
+        mpb.add(self.chunk.written - last_progress_bytes);
+        self.mirrors
+            .report_transfer(mirror_index,
+                             self.chunk.written.saturating_sub(last_report_bytes),
+                             last_report_time.elapsed());
+
+        // The digest covers the whole requested range, so it cannot be checked if the end of the
+        // chunk has been given to another worker meanwhile, or if the request has been stopped
+        if let (Ok(()), Some(expected), Some(md5_hasher)) = (&result, content_md5, md5_hasher) {
+            let computed = Checksum {
+                algorithm: ChecksumAlgorithm::Md5,
+                digest: md5_hasher.finalize(),
+            };
+            if self.chunk.is_complete() && self.chunk.end == requested_end &&
+               computed != expected {
+                // Download the whole range again
+                self.chunk.written = requested_written;
+                self.state_tracker.update(self.chunk_index, self.chunk.written);
+                mpb.set(self.chunk.written);
+                return Err(SnatchError::ChecksumMismatch {
+                               expected,
+                               computed,
+                           });
+            }
+        }
+        result
+    }
+
+    /// Function to get the current end of the chunk, which is moved by the scheduler when
+    /// the second half of the chunk is given to another worker
+    fn refresh_chunk_end(&mut self, mpb: &mut ProgressBar<Pipe>) {
+        let end = self.state_tracker.chunk(self.chunk_index).end;
+        if end != self.chunk.end {
+            self.chunk.end = end;
+            mpb.total = self.chunk.length();
         }
     }
-    mpb.add(sum_bytes - last_progress_bytes);
-    return Ok(0u64);
 }
 
-/// Function to download each chunk of a remote content (given by its URL).
+/// Function to download each remaining chunk of a remote content (given by its mirrors), using a
+/// fixed pool of workers which share the chunks through a `ChunkScheduler`.
 /// This function takes as parameters:
-/// * the remote content length,
-/// * a mutable reference to share between threads, which contains each chunk,
-/// * the number of chunks that contains the remote content,
-/// * the URL of the remote content server,
-/// * a custom authorization to access and download the remote content.
+/// * the remote content informations,
+/// * the local file to write the chunks in,
+/// * the tracker of the download state, which contains the progress of each chunk,
+/// * the mirrors of the remote content, including a custom authorization to access and download
+///   the remote content from each of them,
+/// * the number of workers, each one using its own connection,
+/// * the verifier of the checksums of the pieces, if they are known - a corrupt piece is
+///   downloaded again,
+/// * the settings of the connections: the retry policy, the bandwidth limits and the timeouts.
+///
+/// This function returns an error if a chunk cannot be completely downloaded.
 pub fn download_chunks(cargo_info: CargoInfo,
-                       mut out_file: OutputFileWriter,
-                       nb_chunks: u64,
-                       url: &str)
-                       -> bool {
-    let (content_length, auth_header_factory) = (cargo_info.content_length, cargo_info.auth_header);
+                       out_file: OutputFileWriter,
+                       state_tracker: StateTracker,
+                       mirrors: &MirrorPool,
+                       nb_workers: u64,
+                       piece_verifier: Option<PieceVerifier>,
+                       connection: &ConnectionSettings)
+                       -> Result<(), SnatchError> {
+    let ConnectionSettings {
+        ref retry_policy,
+        ref throttle,
+        ref timeouts,
+    } = *connection;
+    let content_length = cargo_info.content_length.ok_or(SnatchError::UnknownLength)?;
+
+    // The pieces written by a previous download are checked before going on
+    if let Some(ref piece_verifier) = piece_verifier {
+        piece_verifier.verify(&out_file, &state_tracker)?;
+    }
 
-    let global_chunk_length: u64 = (content_length / nb_chunks) + 1;
+    let scheduler = ChunkScheduler::new(state_tracker.clone(), cargo_info.accept_partialcontent);
     let mut jobs = vec![];
 
-    let mut mpb = MultiBar::new();
-    mpb.println(&format!("Downloading {} chunks: ", nb_chunks));
-
-    for chunk_index in 0..nb_chunks {
+    let mut mpb = MultiBar::on(console());
+    mpb.println(&format!("Downloading {} chunks using {} workers: ",
+                         state_tracker.chunks().len(),
+                         nb_workers));
+
+    for worker_index in 0..nb_workers {
+
+        let hyper_client = new_client(timeouts);
+        let scheduler = scheduler.clone();
+        let state_tracker = state_tracker.clone();
+        let chunk_writer_factory = out_file.clone();
+        let mirrors = mirrors.clone();
+        // Each worker uses its own connections, so it needs its own authorization states
+        let auth_header_factories: Vec<_> = mirrors
+            .mirrors()
+            .iter()
+            .map(|mirror| mirror.auth_header.as_ref().map(|factory| factory.fork()))
+            .collect();
+        let check_partialcontent = cargo_info.accept_partialcontent;
+        let retry_policy = retry_policy.clone();
+        let piece_verifier = piece_verifier.clone();
+        let throttle = throttle.for_connection();
+        let min_speed = timeouts.min_speed;
+
+        // Initialize the progress bar for that worker
+        initbar!(mp, mpb, 0, worker_index);
+
+        // In this work, we push a Result value to know if every chunk of the worker is OK
+        jobs.push(thread::spawn(move || {
+            let chunk_writer_factory = chunk_writer_factory;
+
+            while let Some((chunk_index, chunk)) = scheduler.next_chunk() {
+                mp.total = chunk.length();
+                mp.set(chunk.written);
+                mp.message(&format!("Worker {} - chunk {} ", worker_index, chunk_index));
+
+                let added = chunk_writer_factory.add_chunk(chunk_index, &chunk);
+                let mut chunk_download = ChunkDownload {
+                    chunk_index,
+                    chunk_writer: chunk_writer_factory.get_chunk_writer(chunk.start),
+                    chunk,
+                    mirrors: mirrors.clone(),
+                    auth_header_factories: auth_header_factories.clone(),
+                    check_partialcontent,
+                    content_length,
+                    state_tracker: state_tracker.clone(),
+                    retry_policy: retry_policy.clone(),
+                    throttle: throttle.clone(),
+                    min_speed,
+                };
+
+                // Check the pieces this chunk has completed, before giving it back
+                let result = added
+                    .and_then(|_| chunk_download.download(&hyper_client, &mut mp))
+                    .and_then(|_| match piece_verifier {
+                                  Some(ref piece_verifier) => {
+                                      piece_verifier.verify(&chunk_writer_factory,
+                                                            &state_tracker)
+                                  }
+                                  None => Ok(()),
+                              });
+                scheduler.release(chunk_index);
+
+                match result {
+                    // The whole remote content is downloaded again, once every worker stops
+                    Err(error @ SnatchError::RangeNotHonored) |
+                    Err(error @ SnatchError::ContentChanged) => {
+                        mp.finish();
+                        return Err(error);
+                    }
+                    Err(error) => {
+                        mp.finish();
+                        error!(&format!("Cannot download the chunk {}, due to error {}",
+                                        chunk_index,
+                                        error));
+                        return Err(error);
+                    }
+                    Ok(()) => {}
+                }
+            }
 
-        let (mut http_header, RangeBytes(chunk_offset, chunk_length)) =
-            get_header_from_index(chunk_index, content_length, global_chunk_length).unwrap();
-        let hyper_client = Client::new();
-        let url_clone = String::from(url);
-        if let Some(auth_header_factory) = auth_header_factory.clone() {
-            http_header.set(auth_header_factory.build_header());
-        }
-        let monothreading = cargo_info.accept_partialcontent;
+            mp.finish();
+            Ok(())
+        }));
+    }
 
-        // Initialize the progress bar for that chunk
-        initbar!(mp, mpb, chunk_length, chunk_index);
+    mpb.listen();
 
-        let chunk_writer = out_file.get_chunk_writer(chunk_offset);
+    // Contain the result state for workers
+    let mut child_results: Vec<Result<(), SnatchError>> = Vec::with_capacity(nb_workers as usize);
 
-        // In this work, we push a boolean value to know if the chunk is OK
-        jobs.push(thread::spawn(move || match download_a_chunk(&hyper_client,
-                                                               http_header,
-                                                               chunk_writer,
-                                                               &url_clone,
-                                                               &mut mp,
-                                                               monothreading) {
-                                    Ok(bytes_written) => {
-            mp.finish();
-            if bytes_written == 0 {
-                error!(&format!("The downloaded chunk {} is empty", chunk_index));
+    for child in jobs {
+        match child.join() {
+            Ok(result) => child_results.push(result),
+            Err(_) => {
+                let error = io::Error::other("a download worker has stopped unexpectedly");
+                child_results.push(Err(SnatchError::Io(error)))
             }
-            return true;
         }
-                                    Err(error) => {
-            mp.finish();
-            error!(&format!("Cannot download the chunk {}, due to error {}",
-                            chunk_index,
-                            error));
-            return false;
+    }
+
+    // A remote server which does not honor the requested ranges sends the whole remote content
+    // instead, so it is downloaded again using a single connection
+    let range_not_honored = child_results
+        .iter()
+        .any(|result| matches!(*result, Err(SnatchError::RangeNotHonored)));
+    if range_not_honored {
+        warning!("The remote server does not honor the requested ranges! \
+                  Downloading the remote file using one connection.");
+        let received = download_stream(out_file.clone(), mirrors, connection)?;
+        if received != content_length {
+            return Err(SnatchError::LengthMismatch {
+                           expected: content_length,
+                           received,
+                       });
         }
-                                }));
+        for (chunk_index, chunk) in state_tracker.chunks().iter().enumerate() {
+            state_tracker.update(chunk_index, chunk.length());
+        }
+        child_results.clear();
     }
 
-    mpb.listen();
+    if state_tracker.save().is_err() {
+        warning!("Cannot save the download state file!");
+    }
 
-    // Contain the result state for chunks
-    let mut child_results: Vec<bool> = Vec::with_capacity(nb_chunks as usize);
+    // Check if all workers are OK, and if every chunk is completely written to the local file
+    for result in child_results {
+        result?;
+    }
+    if let Some(ref piece_verifier) = piece_verifier {
+        piece_verifier.verify(&out_file, &state_tracker)?;
+    }
+    let chunks = state_tracker.chunks();
+    if !chunks.iter().all(|chunk| chunk.is_complete()) {
+        return Err(SnatchError::LengthMismatch {
+                       expected: content_length,
+                       received: chunks.iter().map(|chunk| chunk.written).sum(),
+                   });
+    }
+    Ok(())
+}
 
-    for child in jobs {
-        match child.join() {
-            Ok(b) => child_results.push(b),
-            Err(_) => child_results.push(false),
+/// Function to download a remote content of unknown length - e.g. sent using the chunked
+/// transfer encoding, or ended by the close of the connection - using a single connection.
+/// The local file grows as the content is received, and the progress bar shows the received
+/// bytes and the speed only.
+/// Such a download cannot be resumed: each attempt downloads the remote content from scratch,
+/// from the mirror chosen by the mirror pool, and is aborted if its throughput stays under the
+/// minimum speed.
+/// This function returns the number of downloaded Bytes.
+pub fn download_stream(out_file: OutputFileWriter,
+                       mirrors: &MirrorPool,
+                       connection: &ConnectionSettings)
+                       -> Result<Bytes, SnatchError> {
+    let ConnectionSettings {
+        ref retry_policy,
+        ref throttle,
+        ref timeouts,
+    } = *connection;
+    let http_client = new_client(timeouts);
+    let throttle = throttle.for_connection();
+    // The total is unknown, so the progress bar never reaches it
+    let mut pb = ProgressBar::on(console(), Bytes::MAX);
+    pb.tick_format("▏▎▍▌▋▊▉██▉▊▋▌▍▎▏");
+    pb.show_tick = true;
+    pb.show_bar = false;
+    pb.show_speed = true;
+    pb.show_percent = false;
+    pb.show_counter = false;
+    pb.show_time_left = false;
+    pb.set_units(Units::Bytes);
+
+    let mut attempts = 0;
+    let mut written;
+    let result = loop {
+        let mirror_index = mirrors.acquire();
+        let mirror = &mirrors.mirrors()[mirror_index];
+        written = 0;
+        let result = stream_to_file(&http_client,
+                                    mirror,
+                                    &out_file,
+                                    &mut written,
+                                    &throttle,
+                                    timeouts.min_speed,
+                                    &mut pb);
+        mirrors.release(mirror_index);
+
+        match result {
+            Ok(()) => break Ok(()),
+            Err(error) => {
+                attempts += 1;
+                let demoted = mirrors.report_failure(mirror_index, &error);
+                if !(error.is_retryable() || demoted) || !retry_policy.should_retry(attempts) {
+                    break Err(error);
+                }
+                pb.message(&format!("Retry {}/{} - ", attempts, retry_policy.max_attempts - 1));
+                thread::sleep(retry_policy.delay(attempts));
+                pb.set(0);
+            }
         }
+    };
+
+    // Do not let the progress bar jump to its unknown total
+    pb.total = written;
+    pb.message(&format!("{} received - ", format_filesize(written)));
+    pb.set(written);
+    pb.finish_println("");
+
+    result?;
+    // A failed attempt may have written further than the remote content
+    out_file.set_len(written)?;
+    Ok(written)
+}
+
+/// Function to send one request for the whole remote content to the given mirror, and to write
+/// its content in the local file, from its beginning, until the end of the response
+fn stream_to_file(http_client: &Client,
+                  mirror: &Mirror,
+                  out_file: &OutputFileWriter,
+                  written: &mut Bytes,
+                  throttle: &Throttle,
+                  min_speed: Option<MinSpeed>,
+                  pb: &mut ProgressBar<Box<dyn Write + Send>>)
+                  -> Result<(), SnatchError> {
+    let mut body = send_authorized_request(http_client,
+                                           &mirror.url,
+                                           Headers::new(),
+                                           &mirror.auth_header)?;
+    if !body.status.is_success() {
+        return Err(SnatchError::HttpStatus(body.status));
     }
 
-    // Check if all chunks are OK
-    return child_results.iter().all(|x| *x);
+    let mut bytes_buffer = [0; DOWNLOAD_BUFFER_BYTES];
+    let progress_update_interval = Duration::from_millis(PROGRESS_UPDATE_INTERVAL_MILLIS);
+    let mut last_progress_time = Instant::now() - progress_update_interval;
+    let mut watchdog = SpeedWatchdog::new(min_speed);
+    let mut chunk_writer = out_file.get_chunk_writer(0);
+
+    loop {
+        let n = match body.read(&mut bytes_buffer) {
+            Ok(0) => return chunk_writer.flush(),
+            Ok(n) => n,
+            Err(error) => return Err(read_error(error)),
+        };
+        chunk_writer.write(*written, &bytes_buffer[0..n])?;
+        *written += n as Bytes;
+        let throttle_start = Instant::now();
+        throttle.consume(n as Bytes);
+        watchdog.pause(throttle_start.elapsed());
+        watchdog.check(n as Bytes)?;
+
+        // Update the CLI
+        if Instant::now().duration_since(last_progress_time) > progress_update_interval {
+            last_progress_time = Instant::now();
+            pb.message(&format!("{} received - ", format_filesize(*written)));
+            pb.set(*written);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -240,23 +747,108 @@ mod test_chunk_length {
 
 }
 
+#[cfg(test)]
+mod test_split_in_chunks {
+
+    use super::split_in_chunks;
+    use state::ChunkState;
+
+    #[test]
+    fn split_in_chunks_should_cover_the_whole_content() {
+        assert_eq!(vec![ChunkState::new(0, 333),
+                        ChunkState::new(334, 667),
+                        ChunkState::new(668, 999)],
+                   split_in_chunks(1000, 3));
+    }
+
+    #[test]
+    fn split_in_chunks_should_keep_the_last_byte() {
+        assert_eq!(vec![ChunkState::new(0, 1), ChunkState::new(2, 2)],
+                   split_in_chunks(3, 2));
+    }
+
+    #[test]
+    fn split_in_more_chunks_than_bytes() {
+        assert_eq!(vec![ChunkState::new(0, 0), ChunkState::new(1, 1)],
+                   split_in_chunks(2, 4));
+    }
+
+    #[test]
+    fn split_without_content_should_be_empty() {
+        assert!(split_in_chunks(0, 4).is_empty());
+    }
+
+}
+
 #[cfg(test)]
 mod test_header {
 
-    use super::{get_header_from_index, RangeBytes};
+    use super::get_header_from_chunk;
     use hyper::header::{ByteRangeSpec, Headers, Range};
+    use state::ChunkState;
 
     #[test]
-    fn wrong_chunk_length_should_return_none() {
-        assert_eq!(None, get_header_from_index(0, 0, 0));
+    fn new_chunk_should_return_a_header_for_the_whole_chunk() {
+        let mut test_header = Headers::new();
+        test_header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(750, 997)]));
+        assert_eq!(test_header, get_header_from_chunk(&ChunkState::new(750, 997)));
     }
 
     #[test]
-    fn good_chunk_length_should_return_a_good_header() {
+    fn started_chunk_should_return_a_header_for_the_remaining_bytes() {
+        let mut chunk = ChunkState::new(750, 997);
+        chunk.written = 50;
         let mut test_header = Headers::new();
-        test_header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(750, 997)]));
-        assert_eq!(Some((test_header, RangeBytes(750, 247))),
-                   get_header_from_index(3, 998, 250));
+        test_header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(800, 997)]));
+        assert_eq!(test_header, get_header_from_chunk(&chunk));
+    }
+
+}
+
+#[cfg(test)]
+mod test_content_range {
+
+    use hyper::header::Headers;
+    use super::{RangeBytes, check_content_range};
+
+    fn partial_headers(content_range: &str, content_length: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Range", vec![content_range.as_bytes().to_vec()]);
+        headers.set_raw("Content-Length", vec![content_length.as_bytes().to_vec()]);
+        headers
+    }
+
+    #[test]
+    fn requested_range_should_be_accepted() {
+        let headers = partial_headers("bytes 100-199/1000", "100");
+        assert!(check_content_range(&headers, &RangeBytes(100, 199), 1000).is_ok());
+        let headers = partial_headers("bytes 100-199/*", "100");
+        assert!(check_content_range(&headers, &RangeBytes(100, 199), 1000).is_ok());
+    }
+
+    #[test]
+    fn other_range_should_not_be_honored() {
+        let headers = partial_headers("bytes 0-199/1000", "200");
+        assert!(check_content_range(&headers, &RangeBytes(100, 199), 1000).is_err());
+    }
+
+    #[test]
+    fn other_total_length_should_not_be_honored() {
+        let headers = partial_headers("bytes 100-199/2000", "100");
+        assert!(check_content_range(&headers, &RangeBytes(100, 199), 1000).is_err());
+    }
+
+    #[test]
+    fn missing_content_range_should_not_be_honored() {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Length", vec![b"100".to_vec()]);
+        assert!(check_content_range(&headers, &RangeBytes(100, 199), 1000).is_err());
+    }
+
+    #[test]
+    fn wrong_content_length_should_not_be_honored() {
+        let headers = partial_headers("bytes 100-199/1000", "1000");
+        assert!(check_content_range(&headers, &RangeBytes(100, 199), 1000).is_err());
     }
 
 }
@@ -0,0 +1,229 @@
+use Bytes;
+use checksum::Checksum;
+use filesize::format_filesize;
+use hyper;
+use hyper::status::StatusCode;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result};
+use std::io;
+
+/// Enum for the different errors which can occur while downloading a remote content
+#[derive(Debug)]
+pub enum SnatchError {
+    /// The remote server cannot be reached, or the connection has been lost
+    Network(hyper::Error),
+    /// The remote server answered with an unexpected HTTP status
+    HttpStatus(StatusCode),
+    /// The remote content is protected by an authorization which is not supported
+    AuthUnsupported(String),
+    /// The remote content is protected, and no credentials have been given to access it
+    MissingCredentials,
+    /// An error occured while reading or writing a local file
+    Io(io::Error),
+    /// The file system of the local file does not have enough free space for the remote content
+    NotEnoughSpace { needed: Bytes, available: Bytes },
+    /// The remote server did not send the range of bytes which has been asked
+    RangeNotHonored,
+    /// The remote server did not send the expected number of bytes
+    LengthMismatch { expected: Bytes, received: Bytes },
+    /// The remote content has changed since the beginning of the download
+    ContentChanged,
+    /// The connection stayed slower than the minimum speed (in bytes per second)
+    TooSlow { speed: Bytes, min_speed: Bytes },
+    /// Too many redirections have been followed to get the remote content
+    TooManyRedirects(u32),
+    /// The remote server redirects to an URL which is not valid, or not allowed
+    InvalidRedirect(String),
+    /// The remote server did not send the length of the remote content
+    UnknownLength,
+    /// The download has been cancelled by the user
+    Cancelled,
+    /// The expected checksum of the remote content is not valid, or cannot be found
+    InvalidChecksum(String),
+    /// The downloaded file does not have the expected checksum
+    ChecksumMismatch {
+        expected: Checksum,
+        computed: Checksum,
+    },
+    /// A line of the input list file cannot be read
+    InvalidInputList { line: usize, message: String },
+    /// The Metalink file cannot be read
+    InvalidMetalink(String),
+}
+
+impl SnatchError {
+    /// Check if the same request could succeed if it is sent again
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            SnatchError::Network(_) |
+            SnatchError::LengthMismatch { .. } |
+            SnatchError::TooSlow { .. } |
+            SnatchError::ChecksumMismatch { .. } => true,
+            // A local error, such as a full disk, would happen again
+            SnatchError::Io(ref error) => is_network_io_error(error),
+            SnatchError::HttpStatus(status) => {
+                status.is_server_error() || status == StatusCode::RequestTimeout ||
+                status == StatusCode::TooManyRequests
+            }
+            _ => false,
+        }
+    }
+
+    /// Check if the error comes from the local side, such as a full disk, rather than from the
+    /// remote server
+    pub fn is_local(&self) -> bool {
+        match *self {
+            SnatchError::Io(ref error) => !is_network_io_error(error),
+            SnatchError::NotEnoughSpace { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Function to check if an I/O error comes from a connection, rather than from a local file
+fn is_network_io_error(error: &io::Error) -> bool {
+    matches!(error.kind(),
+             io::ErrorKind::ConnectionRefused |
+             io::ErrorKind::ConnectionReset |
+             io::ErrorKind::ConnectionAborted |
+             io::ErrorKind::NotConnected |
+             io::ErrorKind::BrokenPipe |
+             io::ErrorKind::TimedOut |
+             io::ErrorKind::WouldBlock |
+             io::ErrorKind::Interrupted |
+             io::ErrorKind::UnexpectedEof)
+}
+
+impl Display for SnatchError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match *self {
+            SnatchError::Network(ref error) => write!(f, "network error: {}", error),
+            SnatchError::HttpStatus(ref status) => {
+                write!(f, "the remote server answered with status {}", status)
+            }
+            SnatchError::AuthUnsupported(ref auth_type) => {
+                write!(f,
+                       "the remote content is protected by {} Authorization, which is not \
+                        supported",
+                       auth_type)
+            }
+            SnatchError::MissingCredentials => {
+                write!(f, "the remote content is protected, and no credentials were given")
+            }
+            SnatchError::Io(ref error) => write!(f, "I/O error: {}", error),
+            SnatchError::ContentChanged => {
+                write!(f, "the remote content has changed while downloading it")
+            }
+            SnatchError::RangeNotHonored => {
+                write!(f, "the remote server did not send the requested range of bytes")
+            }
+            SnatchError::LengthMismatch { expected, received } => {
+                write!(f,
+                       "expected {} bytes from the remote server, but received {}",
+                       expected,
+                       received)
+            }
+            SnatchError::NotEnoughSpace { needed, available } => {
+                write!(f,
+                       "not enough free space on the disk ({} needed, {} available)",
+                       format_filesize(needed),
+                       format_filesize(available))
+            }
+            SnatchError::TooSlow { speed, min_speed } => {
+                write!(f,
+                       "the connection was too slow ({}/s, under {}/s)",
+                       format_filesize(speed),
+                       format_filesize(min_speed))
+            }
+            SnatchError::TooManyRedirects(max_redirects) => {
+                write!(f, "more than {} redirections", max_redirects)
+            }
+            SnatchError::InvalidRedirect(ref url) => write!(f, "invalid redirection to {}", url),
+            SnatchError::UnknownLength => {
+                write!(f, "cannot get the length of the remote content")
+            }
+            SnatchError::Cancelled => write!(f, "the download has been cancelled"),
+            SnatchError::InvalidChecksum(ref checksum) => {
+                write!(f, "invalid or missing checksum: {}", checksum)
+            }
+            SnatchError::ChecksumMismatch {
+                ref expected,
+                ref computed,
+            } => write!(f, "expected checksum {}, but computed {}", expected, computed),
+            SnatchError::InvalidInputList { line, ref message } => {
+                write!(f, "invalid input list at line {}: {}", line, message)
+            }
+            SnatchError::InvalidMetalink(ref message) => write!(f, "invalid metalink: {}", message),
+        }
+    }
+}
+
+impl Error for SnatchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SnatchError::Network(ref error) => Some(error),
+            SnatchError::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<hyper::Error> for SnatchError {
+    fn from(error: hyper::Error) -> SnatchError {
+        SnatchError::Network(error)
+    }
+}
+
+impl From<io::Error> for SnatchError {
+    fn from(error: io::Error) -> SnatchError {
+        SnatchError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod test_snatch_error {
+
+    use hyper::status::StatusCode;
+    use libc;
+    use std::io;
+    use super::SnatchError;
+
+    #[test]
+    fn server_errors_should_be_retryable() {
+        assert!(SnatchError::HttpStatus(StatusCode::ServiceUnavailable).is_retryable());
+        assert!(SnatchError::LengthMismatch {
+                    expected: 10,
+                    received: 5,
+                }
+                .is_retryable());
+        assert!(SnatchError::TooSlow {
+                    speed: 100,
+                    min_speed: 10240,
+                }
+                .is_retryable());
+        assert!(SnatchError::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))
+                    .is_retryable());
+    }
+
+    #[test]
+    fn client_errors_should_not_be_retryable() {
+        assert!(!SnatchError::HttpStatus(StatusCode::NotFound).is_retryable());
+        assert!(!SnatchError::RangeNotHonored.is_retryable());
+        assert!(!SnatchError::ContentChanged.is_retryable());
+        assert!(!SnatchError::NotEnoughSpace {
+                     needed: 1024,
+                     available: 512,
+                 }
+                 .is_retryable());
+        assert!(!SnatchError::AuthUnsupported(String::from("Digest")).is_retryable());
+    }
+
+    #[test]
+    fn local_io_errors_should_not_be_retryable() {
+        assert!(!SnatchError::Io(io::Error::from_raw_os_error(libc::ENOSPC)).is_retryable());
+        assert!(!SnatchError::Io(io::Error::from_raw_os_error(libc::EACCES)).is_retryable());
+        assert!(!SnatchError::Io(io::Error::from_raw_os_error(libc::EROFS)).is_retryable());
+        assert!(SnatchError::Io(io::Error::from_raw_os_error(libc::ENOSPC)).is_local());
+    }
+
+}
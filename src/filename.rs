@@ -0,0 +1,230 @@
+use hyper::Url;
+
+/// Constant to represent the maximum length (in bytes) of a file name on most file systems
+const MAX_FILE_NAME_BYTES: usize = 255;
+
+/// Constant to represent the length (in bytes) of the longest suffix appended to the local file
+/// name while downloading: `.part.` followed by the index of a part file, up to 20 digits
+const MAX_SUFFIX_BYTES: usize = 26;
+
+/// Constant to represent the characters which cannot be used in a file name on some platforms
+const RESERVED_CHARACTERS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Constant to represent the names of the devices of Windows, which cannot be used as file names
+const RESERVED_NAMES: &[&str] = &["CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4",
+                                  "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3",
+                                  "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"];
+
+/// Function to get the file name given by the value of a `Content-Disposition` header, using its
+/// `filename*` parameter (RFC 5987) if any, else its `filename` parameter.
+/// The file name is sanitized, and None is returned if it cannot be used.
+pub fn content_disposition_file_name(value: &str) -> Option<String> {
+    let mut file_name = None;
+    let mut extended_file_name = None;
+
+    for parameter in split_parameters(value).into_iter().skip(1) {
+        let mut parameter_parts = parameter.splitn(2, '=');
+        let name = parameter_parts.next().unwrap_or("").trim().to_lowercase();
+        let value = parameter_parts.next().unwrap_or("").trim();
+        match name.as_str() {
+            "filename" => file_name = Some(unquote(value)),
+            "filename*" => extended_file_name = decode_extended_value(value),
+            _ => {}
+        }
+    }
+
+    extended_file_name
+        .or(file_name)
+        .and_then(|file_name| sanitize_file_name(&file_name))
+}
+
+/// Function to get the file name of an URL: the last segment of its path, without its query, and
+/// percent-decoded.
+/// The file name is sanitized, and None is returned if it cannot be used (e.g. if the path of the
+/// URL ends with a `/`).
+pub fn url_file_name(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let segment = url.path_segments()?.next_back()?;
+    sanitize_file_name(&String::from_utf8_lossy(&percent_decode(segment)))
+}
+
+/// Function to make a file name received from a remote server safe to use as a local file name:
+/// it cannot contain a path, reserved or control characters, or be a hidden file, and its length
+/// is limited so the partial, state and part files of the download can be named after it.
+/// None is returned if nothing is left of the file name.
+pub fn sanitize_file_name(file_name: &str) -> Option<String> {
+    // Only the last component is kept, so the file cannot be written in another directory
+    let file_name = file_name.rsplit(['/', '\\']).next().unwrap_or("");
+    let file_name: String = file_name
+        .chars()
+        .map(|c| if c.is_control() || RESERVED_CHARACTERS.contains(&c) {
+                 '_'
+             } else {
+                 c
+             })
+        .collect();
+    let file_name = file_name
+        .trim()
+        .trim_start_matches('.')
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+    if file_name.is_empty() {
+        return None;
+    }
+
+    let stem = file_name.split('.').next().unwrap_or("");
+    let mut file_name = if RESERVED_NAMES
+           .iter()
+           .any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+        format!("_{}", file_name)
+    } else {
+        String::from(file_name)
+    };
+    if file_name.len() > MAX_FILE_NAME_BYTES - MAX_SUFFIX_BYTES {
+        let mut length = MAX_FILE_NAME_BYTES - MAX_SUFFIX_BYTES;
+        while !file_name.is_char_boundary(length) {
+            length -= 1;
+        }
+        file_name.truncate(length);
+    }
+    Some(file_name)
+}
+
+/// Function to split the value of a header in its parameters, separated by `;` out of quoted
+/// strings
+fn split_parameters(value: &str) -> Vec<&str> {
+    let mut parameters = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parameters.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parameters.push(&value[start..]);
+    parameters
+}
+
+/// Function to get the content of a parameter value, which may be a quoted string
+fn unquote(value: &str) -> String {
+    if !(value.len() >= 2 && value.starts_with('"') && value.ends_with('"')) {
+        return String::from(value);
+    }
+    let mut unquoted = String::new();
+    let mut escaped = false;
+    for c in value[1..value.len() - 1].chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+        } else {
+            unquoted.push(c);
+            escaped = false;
+        }
+    }
+    unquoted
+}
+
+/// Function to decode an extended parameter value (RFC 5987), as `charset'language'value`, where
+/// the value is percent-encoded.
+/// Only the UTF-8 and ISO-8859-1 charsets are supported.
+fn decode_extended_value(value: &str) -> Option<String> {
+    let mut value_parts = value.splitn(3, '\'');
+    let charset = value_parts.next()?.to_lowercase();
+    let _language = value_parts.next()?;
+    let bytes = percent_decode(value_parts.next()?);
+    match charset.as_str() {
+        "utf-8" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" => Some(bytes.into_iter().map(char::from).collect()),
+        _ => None,
+    }
+}
+
+/// Function to decode the percent-encoded bytes of a string.
+/// An invalid escape is kept as is.
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = if bytes[index] == b'%' {
+            value
+                .get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod test_filename {
+
+    use sink::part_file_path;
+    use state::DownloadState;
+    use std::path::Path;
+    use super::{MAX_FILE_NAME_BYTES, content_disposition_file_name, sanitize_file_name,
+                url_file_name};
+
+    #[test]
+    fn url_file_name_should_be_decoded_without_query() {
+        assert_eq!(Some(String::from("my file.zip")),
+                   url_file_name("http://localhost/files/my%20file.zip?token=a%2Fb#top"));
+        assert_eq!(Some(String::from("café.txt")),
+                   url_file_name("http://localhost/caf%C3%A9.txt"));
+        assert_eq!(None, url_file_name("http://localhost/files/"));
+        assert_eq!(None, url_file_name("http://localhost"));
+        assert_eq!(Some(String::from("passwd")),
+                   url_file_name("http://localhost/%2E%2E%2F%2E%2E%2Fetc%2Fpasswd"));
+    }
+
+    #[test]
+    fn extended_file_name_should_be_preferred() {
+        assert_eq!(Some(String::from("€ rates.pdf")),
+                   content_disposition_file_name("attachment; filename=\"EURO rates.pdf\"; \
+                                                  filename*=UTF-8''%e2%82%ac%20rates.pdf"));
+        assert_eq!(Some(String::from("file; _1_.zip")),
+                   content_disposition_file_name("attachment; filename=\"file; \\\"1\\\".zip\""));
+        assert_eq!(Some(String::from("naïve.txt")),
+                   content_disposition_file_name("attachment; \
+                                                  filename*=iso-8859-1'en'na%EFve.txt"));
+        assert_eq!(None, content_disposition_file_name("inline"));
+    }
+
+    #[test]
+    fn unsafe_file_name_should_be_sanitized() {
+        assert_eq!(Some(String::from("passwd")), sanitize_file_name("../../etc/passwd"));
+        assert_eq!(Some(String::from("evil.exe")), sanitize_file_name("C:\\Windows\\evil.exe"));
+        assert_eq!(Some(String::from("a_b_c.txt")), sanitize_file_name("a:b\u{7}c.txt"));
+        assert_eq!(Some(String::from("bashrc")), sanitize_file_name(".bashrc"));
+        assert_eq!(Some(String::from("_con.txt")), sanitize_file_name("con.txt"));
+        assert_eq!(None, sanitize_file_name(".."));
+        assert_eq!(None, sanitize_file_name("files/"));
+        assert_eq!(228, sanitize_file_name(&"é".repeat(200)).unwrap().len());
+    }
+
+    #[test]
+    fn part_files_of_the_longest_file_name_should_fit_in_a_file_name() {
+        let local_name = sanitize_file_name(&"a".repeat(300)).unwrap();
+        let part_path = DownloadState::part_path(Path::new(&local_name));
+        let part_file = part_file_path(&part_path, usize::MAX);
+        assert!(part_file.as_os_str().len() <= MAX_FILE_NAME_BYTES);
+    }
+
+}
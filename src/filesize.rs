@@ -41,3 +41,48 @@ pub fn format_filesize(size: u64) -> String {
 
     format!("{:.2} {}", file_size, unit)
 }
+
+/// Function to parse a size, given as a number of bytes optionally followed by one of the units
+/// of `format_filesize` (e.g. `5M`, `500 KB` or `1.5GB`). The units are case-insensitive.
+pub fn parse_filesize(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let unit_index = size.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(size.len());
+    let (number, unit) = size.split_at(unit_index);
+
+    let number: f64 = number.parse().ok()?;
+    let unit = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => FileSize::B,
+        "K" | "KB" => FileSize::KB,
+        "M" | "MB" => FileSize::MB,
+        "G" | "GB" => FileSize::GB,
+        "T" | "TB" => FileSize::TB,
+        _ => return None,
+    };
+    Some((number * unit.value() as f64).round() as u64)
+}
+
+#[cfg(test)]
+mod test_filesize {
+
+    use super::{format_filesize, parse_filesize};
+
+    #[test]
+    fn size_with_unit_should_be_parsed() {
+        assert_eq!(Some(5 * 1_048_576), parse_filesize("5M"));
+        assert_eq!(Some(500 * 1_024), parse_filesize("500 kb"));
+        assert_eq!(Some(1_610_612_736), parse_filesize("1.5GB"));
+        assert_eq!(Some(1000), parse_filesize("1000"));
+    }
+
+    #[test]
+    fn formatted_size_should_be_parsed() {
+        assert_eq!(Some(5 * 1_048_576), parse_filesize(&format_filesize(5 * 1_048_576)));
+    }
+
+    #[test]
+    fn invalid_size_should_not_be_parsed() {
+        assert_eq!(None, parse_filesize("5 parsecs"));
+        assert_eq!(None, parse_filesize("M"));
+    }
+
+}
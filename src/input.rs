@@ -0,0 +1,150 @@
+use Bytes;
+use checksum::Checksum;
+use error::SnatchError;
+use pieces::Pieces;
+
+/// Structure that contains a remote content to download, with the options given for it only
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadRequest {
+    pub url: String,
+    /// The local file to save the remote content, if it is not guessed from the URL
+    pub file: Option<String>,
+    /// The expected checksum of the remote content, as given to `--checksum`
+    pub checksum: Option<String>,
+    /// Other URLs which provide the same remote content
+    pub mirrors: Vec<String>,
+    /// The expected length of the remote content, if it is known before downloading it
+    pub size: Option<Bytes>,
+    /// Other expected checksums of the remote content, as given by a Metalink file
+    pub checksums: Vec<Checksum>,
+    /// The expected checksums of the pieces of the remote content
+    pub pieces: Option<Pieces>,
+}
+
+impl DownloadRequest {
+    pub fn new(url: &str) -> DownloadRequest {
+        DownloadRequest {
+            url: String::from(url),
+            file: None,
+            checksum: None,
+            mirrors: vec![],
+            size: None,
+            checksums: vec![],
+            pieces: None,
+        }
+    }
+
+    /// Function to set an option of this request, given as `name=value`
+    fn set_option(&mut self, option: &str) -> Result<(), String> {
+        let mut option_parts = option.splitn(2, '=');
+        match (option_parts.next(), option_parts.next()) {
+            (Some("out"), Some(file)) if !file.is_empty() => self.file = Some(String::from(file)),
+            (Some("checksum"), Some(checksum)) if !checksum.is_empty() => {
+                self.checksum = Some(String::from(checksum))
+            }
+            (Some("mirror"), Some(mirror)) if !mirror.is_empty() => {
+                self.mirrors.push(String::from(mirror))
+            }
+            _ => return Err(format!("unknown option {}", option)),
+        }
+        Ok(())
+    }
+}
+
+/// Function to get the downloads of an input list file.
+/// Each line contains an URL, optionally followed by `out=<file>`, `checksum=<checksum>` and
+/// `mirror=<URL>` options. The options can also be given on the next lines, if these lines are
+/// indented.
+/// The empty lines, and the lines starting with `#`, are ignored.
+pub fn parse_input_list(content: &str) -> Result<Vec<DownloadRequest>, SnatchError> {
+    let mut requests: Vec<DownloadRequest> = vec![];
+
+    for (line_index, line) in content.lines().enumerate() {
+        let invalid_line = |message: String| {
+            SnatchError::InvalidInputList {
+                line: line_index + 1,
+                message,
+            }
+        };
+
+        let trimmed_line = line.trim();
+        if trimmed_line.is_empty() || trimmed_line.starts_with('#') {
+            continue;
+        }
+        let mut words = trimmed_line.split_whitespace();
+
+        // An indented line contains the options of the previous URL
+        let is_option_line = line.starts_with(char::is_whitespace);
+        if !is_option_line {
+            requests.push(DownloadRequest::new(words.next().unwrap_or("")));
+        }
+        let request = requests
+            .last_mut()
+            .ok_or_else(|| invalid_line(String::from("options without URL")))?;
+        for option in words {
+            request.set_option(option).map_err(&invalid_line)?;
+        }
+    }
+
+    Ok(requests)
+}
+
+/// Function to get the URLs of a mirrors file: one URL per line.
+/// The empty lines, and the lines starting with `#`, are ignored.
+pub fn parse_mirror_list(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod test_input_list {
+
+    use super::{DownloadRequest, parse_input_list};
+
+    #[test]
+    fn input_list_should_give_one_request_per_url() {
+        let content = "# Release files\n\
+                       http://localhost/file.zip\n\
+                       \n\
+                       http://localhost/file.tar.gz out=archive.tar.gz checksum=sha256:SHA256SUMS\n\
+                       http://localhost/file.deb\n  out=package.deb\n  \
+                       mirror=http://mirror/file.deb\n";
+        let requests = parse_input_list(content).unwrap();
+
+        assert_eq!(vec![DownloadRequest::new("http://localhost/file.zip"),
+                        DownloadRequest {
+                            url: String::from("http://localhost/file.tar.gz"),
+                            file: Some(String::from("archive.tar.gz")),
+                            checksum: Some(String::from("sha256:SHA256SUMS")),
+                            mirrors: vec![],
+                            size: None,
+                            checksums: vec![],
+                            pieces: None,
+                        },
+                        DownloadRequest {
+                            url: String::from("http://localhost/file.deb"),
+                            file: Some(String::from("package.deb")),
+                            checksum: None,
+                            mirrors: vec![String::from("http://mirror/file.deb")],
+                            size: None,
+                            checksums: vec![],
+                            pieces: None,
+                        }],
+                   requests);
+    }
+
+    #[test]
+    fn unknown_option_should_be_an_error() {
+        assert!(parse_input_list("http://localhost/file.zip dir=/tmp").is_err());
+    }
+
+    #[test]
+    fn options_without_url_should_be_an_error() {
+        assert!(parse_input_list("  out=file.zip").is_err());
+    }
+
+}
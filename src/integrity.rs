@@ -0,0 +1,148 @@
+use base64;
+use checksum::{Checksum, ChecksumAlgorithm};
+use hyper::header::Headers;
+
+/// Trait to extend functionalities of the Headers type, from `hyper`
+pub trait GetIntegrityChecksums {
+    /// Function to get the checksums of the whole remote content, sent by the remote server in
+    /// the `Digest` (RFC 3230), `Repr-Digest` (RFC 9530) and `Content-MD5` headers.
+    fn get_representation_checksums(&self) -> Vec<Checksum>;
+
+    /// Function to get the MD5 digest of the body of this response only, sent by the remote
+    /// server in the `Content-MD5` header.
+    /// For a PartialContent response, this is the digest of the requested range.
+    fn get_content_md5(&self) -> Option<Checksum>;
+}
+
+impl GetIntegrityChecksums for Headers {
+    fn get_representation_checksums(&self) -> Vec<Checksum> {
+        let mut checksums = vec![];
+
+        // Digest: SHA-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=, MD5=...
+        for (name, value) in get_header_items(self, "Digest") {
+            if let Some(checksum) = parse_checksum(&name, &value) {
+                checksums.push(checksum);
+            }
+        }
+
+        // Repr-Digest: sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:
+        for (name, value) in get_header_items(self, "Repr-Digest") {
+            // The parameters of the dictionary member are not used
+            let value = value.split(';').next().unwrap_or("").trim();
+            if value.len() >= 2 && value.starts_with(':') && value.ends_with(':') {
+                if let Some(checksum) = parse_checksum(&name, &value[1..value.len() - 1]) {
+                    checksums.push(checksum);
+                }
+            }
+        }
+
+        if let Some(checksum) = self.get_content_md5() {
+            checksums.push(checksum);
+        }
+
+        // The same digest may be sent in several headers
+        checksums.dedup();
+        checksums
+    }
+
+    fn get_content_md5(&self) -> Option<Checksum> {
+        get_header_value(self, "Content-MD5").and_then(|value| parse_checksum("MD5", &value))
+    }
+}
+
+/// Function to get the value of a header, all of its lines being joined with commas
+fn get_header_value(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name).map(|raw| {
+                                  raw.iter()
+                                      .map(|line| String::from_utf8_lossy(line).into_owned())
+                                      .collect::<Vec<String>>()
+                                      .join(",")
+                              })
+}
+
+/// Function to get the `name=value` items of a comma-separated header
+fn get_header_items(headers: &Headers, name: &str) -> Vec<(String, String)> {
+    get_header_value(headers, name)
+        .map(|value| {
+            value
+                .split(',')
+                .filter_map(|item| {
+                    let mut item_parts = item.splitn(2, '=');
+                    match (item_parts.next(), item_parts.next()) {
+                        (Some(name), Some(value)) => {
+                            Some((String::from(name.trim()), String::from(value.trim())))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Function to get a checksum from the name of its algorithm, as registered for the HTTP digest
+/// headers, and its base64 digest.
+/// This function returns None if the algorithm is not supported or the digest is not valid.
+fn parse_checksum(algorithm_name: &str, base64_digest: &str) -> Option<Checksum> {
+    let algorithm = if algorithm_name.eq_ignore_ascii_case("SHA") {
+        Some(ChecksumAlgorithm::Sha1)
+    } else {
+        ChecksumAlgorithm::from_name(algorithm_name)
+    };
+    let digest = base64::decode(base64_digest.trim()).ok();
+    match (algorithm, digest) {
+        (Some(algorithm), Some(digest)) if digest.len() == algorithm.digest_length() => {
+            Some(Checksum {
+                     algorithm,
+                     digest,
+                 })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_integrity {
+
+    use checksum::{Checksum, ChecksumAlgorithm};
+    use hyper::header::Headers;
+    use super::GetIntegrityChecksums;
+
+    /// Checksums of "hello world"
+    fn sha256() -> Checksum {
+        Checksum::compute(ChecksumAlgorithm::Sha256, b"hello world")
+    }
+
+    fn md5() -> Checksum {
+        Checksum::compute(ChecksumAlgorithm::Md5, b"hello world")
+    }
+
+    #[test]
+    fn digest_header_should_give_the_supported_checksums() {
+        let mut headers = Headers::new();
+        headers.set_raw("Digest",
+                        vec![b"UNIXsum=30637, SHA-256=uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="
+                                 .to_vec()]);
+        assert_eq!(vec![sha256()], headers.get_representation_checksums());
+    }
+
+    #[test]
+    fn repr_digest_header_should_give_the_supported_checksums() {
+        let mut headers = Headers::new();
+        headers.set_raw("Repr-Digest",
+                        vec![b"sha-256=:uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=:, \
+                               sha-512=:invalid:"
+                                 .to_vec()]);
+        headers.set_raw("Content-MD5", vec![b"XrY7u+Ae7tCTyyK7j1rNww==".to_vec()]);
+        assert_eq!(vec![sha256(), md5()], headers.get_representation_checksums());
+    }
+
+    #[test]
+    fn content_md5_header_should_give_the_md5_checksum() {
+        let mut headers = Headers::new();
+        assert_eq!(None, headers.get_content_md5());
+        headers.set_raw("Content-MD5", vec![b"XrY7u+Ae7tCTyyK7j1rNww==".to_vec()]);
+        assert_eq!(Some(md5()), headers.get_content_md5());
+    }
+
+}
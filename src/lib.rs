@@ -1,6 +1,15 @@
+extern crate base64;
+extern crate blake3;
 extern crate hyper;
 extern crate pbr;
 extern crate ansi_term;
+extern crate libc;
+extern crate md5;
+extern crate memmap2;
+extern crate rand;
+extern crate roxmltree;
+extern crate sha1;
+extern crate sha2;
 
 use std::sync::{Arc, Mutex};
 
@@ -9,12 +18,28 @@ pub mod logs;
 
 pub mod authorization;
 pub mod cargo_helper;
+pub mod checksum;
 pub mod client;
 pub mod contentlength;
+pub mod credentials;
 pub mod download;
+pub mod error;
+pub mod filename;
 pub mod filesize;
 pub mod http_version;
+pub mod input;
+pub mod integrity;
+pub mod metalink;
+pub mod mirrors;
+pub mod pieces;
+pub mod ratelimit;
+pub mod redirect;
 pub mod response;
+pub mod retry;
+pub mod scheduler;
+pub mod sink;
+pub mod state;
+pub mod timeouts;
 pub mod util;
 pub mod write;
 
@@ -1,53 +1,82 @@
+use std::io;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Print the messages and the progress bars on the standard error, instead of the standard output
+static USE_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Function to print the messages and the progress bars on the standard error, so the standard
+/// output only contains the remote content
+pub fn use_stderr() {
+    USE_STDERR.store(true, Ordering::SeqCst);
+}
+
+/// Function to print a line of the messages
+pub fn print_line(line: &str) {
+    if USE_STDERR.load(Ordering::SeqCst) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Function to get the output the progress bars are drawn on
+pub fn console() -> Box<dyn Write + Send> {
+    if USE_STDERR.load(Ordering::SeqCst) {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+#[macro_export]
 macro_rules! warning {
     ($message:expr) => {{
         use ansi_term::Colour::Yellow;
-        println!("{}",
-            Yellow
-                .bold()
-                .paint("[WARNING] ".to_owned() + $message)
-                .to_string()
-        )
+        $crate::logs::print_line(&Yellow
+            .bold()
+            .paint("[WARNING] ".to_owned() + $message)
+            .to_string())
     }};
 }
 
+#[macro_export]
 macro_rules! error {
     ($message:expr) => {{
         use ansi_term::Colour::Red;
-        println!("{}",
-            Red
-                .bold()
-                .paint("[ERROR] ".to_owned() + $message)
-                .to_string()
-        )
+        $crate::logs::print_line(&Red
+            .bold()
+            .paint("[ERROR] ".to_owned() + $message)
+            .to_string())
     }};
 }
 
+#[allow(unused_macros)]
+#[macro_export]
 macro_rules! epanic {
     ($message:expr) => {{
         panic!(error!($message));
     }};
 }
 
+#[macro_export]
 macro_rules! info {
     ($message:expr) => {{
         use ansi_term::Colour::White;
-        println!("{}",
-            White
-                .bold()
-                .paint("[DEBUG] ".to_owned() + $message)
-                .to_string()
-        )
+        $crate::logs::print_line(&White
+            .bold()
+            .paint("[DEBUG] ".to_owned() + $message)
+            .to_string())
     }};
 }
 
+#[macro_export]
 macro_rules! ok {
     ($message:expr) => {{
         use ansi_term::Colour::Green;
-        println!("{}",
-            Green
-                .bold()
-                .paint("OK! ".to_owned() + $message)
-                .to_string()
-        )
+        $crate::logs::print_line(&Green
+            .bold()
+            .paint("OK! ".to_owned() + $message)
+            .to_string())
     }};
 }
\ No newline at end of file
@@ -2,23 +2,88 @@ extern crate ansi_term;
 #[macro_use]
 extern crate clap;
 extern crate hyper;
+#[macro_use]
 extern crate libsnatch;
 extern crate num_cpus;
 
 use clap::{App, Arg};
-use libsnatch::cargo_helper::get_cargo_info;
-use libsnatch::download::download_chunks;
-use libsnatch::filesize::format_filesize;
-#[macro_use]
-mod logs;
-use libsnatch::util::prompt_user;
-use libsnatch::write::OutputFileWriter;
-use std::fs::{File, remove_file};
+use libsnatch::Bytes;
+use libsnatch::authorization::AuthorizationHeaderFactory;
+use libsnatch::cargo_helper::{CargoInfo, get_cargo_info};
+use libsnatch::checksum::{Checksum, resolve_checksum, verify_file};
+use libsnatch::client::fetch_text;
+use libsnatch::credentials::{CredentialChain, CredentialProvider, Credentials, DEFAULT_TOKEN_VAR,
+                             EnvCredentials, FixedCredentials, NetrcCredentials,
+                             PromptCredentials};
+use libsnatch::download::{ConnectionSettings, download_chunks, download_stream};
+use libsnatch::error::SnatchError;
+use libsnatch::filename::url_file_name;
+use libsnatch::filesize::{format_filesize, parse_filesize};
+use libsnatch::input::{DownloadRequest, parse_input_list, parse_mirror_list};
+use libsnatch::metalink::{MetalinkFile, parse_metalink};
+use libsnatch::logs;
+use libsnatch::mirrors::{MirrorPool, probe_mirrors};
+use libsnatch::pieces::PieceVerifier;
+use libsnatch::ratelimit::Throttle;
+use libsnatch::redirect::{DEFAULT_MAX_REDIRECTS, RedirectPolicy};
+use libsnatch::retry::RetryPolicy;
+use libsnatch::scheduler::CHUNKS_PER_WORKER;
+use libsnatch::sink::{ChunkSink, DEFAULT_REORDER_BUFFER_BYTES, FileSink, MmapSink, OrderedSink,
+                      PartFilesSink, Prealloc, Storage};
+use libsnatch::state::{ChunkState, DownloadState, StateTracker};
+use libsnatch::timeouts::{DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_MIN_SPEED_SECS,
+                          DEFAULT_READ_TIMEOUT_SECS, MinSpeed, Timeouts, parse_duration};
+use libsnatch::util::{available_space, prompt_password, prompt_user};
+use libsnatch::write::{OutputFileWriter, WRITE_BUFFER_BYTES};
+use std::env;
+use std::fs::{File, OpenOptions, create_dir_all, remove_file, rename};
+use std::io::{self, Read};
 use std::path::Path;
 use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
 
 static DEFAULT_FILENAME: &'static str = "index.html";
 
+/// Local file to write the remote content to the standard output
+static STDOUT_FILE: &str = "-";
+
+/// Exit code when the downloaded file does not have the expected checksum
+const CHECKSUM_MISMATCH_EXIT_CODE: i32 = 2;
+
+/// Maximum number of times a download starts again, because the remote content has changed while
+/// downloading it
+const MAX_CONTENT_CHANGES: u32 = 3;
+
+/// Options shared by every download of the same invocation
+struct DownloadOptions {
+    threads: usize,
+    /// The retry policy, the bandwidth limits (shared by every download) and the timeouts
+    connection: ConnectionSettings,
+    redirect_policy: RedirectPolicy,
+    /// The length of the buffer of each connection, to write the received bytes in larger blocks
+    write_buffer: usize,
+    storage: Storage,
+    prealloc: Prealloc,
+    /// The maximum size of the chunks kept in memory while writing to the standard output
+    reorder_buffer: usize,
+    credentials: Option<Credentials>,
+    auth_header: Option<AuthorizationHeaderFactory>,
+    force: bool,
+    debug: bool,
+    /// Preparing a download may ask something to the user, so only one download is prepared at a
+    /// time
+    prepare_lock: Mutex<()>,
+}
+
+/// Enum for the end of a download which did not fail, with its local file
+enum DownloadStatus {
+    Done(String, Bytes),
+    Skipped(String),
+}
+
 fn main() {
 
     // Parse arguments
@@ -30,35 +95,299 @@ fn main() {
                  .long("file")
                  .short("f")
                  .takes_value(true)
-                 .help("The local file to save the remote content file"))
+                 .help("The local file to save the remote content file, or - to write it to \
+                        the standard output (only with one URL)"))
+        .arg(Arg::with_name("output")
+                 .short("O")
+                 .takes_value(true)
+                 .conflicts_with("file")
+                 .help("Same as --file"))
+        .arg(Arg::with_name("stdout")
+                 .long("stdout")
+                 .conflicts_with_all(&["file", "output"])
+                 .help("Write the remote content to the standard output, same as --file -"))
+        .arg(Arg::with_name("reorder_buffer")
+                 .long("reorder-buffer")
+                 .takes_value(true)
+                 .help("Maximum size of the chunks received ahead which are kept in memory while \
+                        writing to the standard output, beyond it they are written in a \
+                        temporary file (default: 64M)"))
         .arg(Arg::with_name("threads")
                  .long("threads")
                  .short("t")
                  .takes_value(true)
                  .help("Threads which can use to download"))
+        .arg(Arg::with_name("jobs")
+                 .long("jobs")
+                 .short("j")
+                 .takes_value(true)
+                 .help("Number of files to download at the same time (default: 1)"))
+        .arg(Arg::with_name("input_file")
+                 .long("input-file")
+                 .short("i")
+                 .takes_value(true)
+                 .help("A file which contains the URLs to download, one per line, optionally \
+                        followed by out=<file> and checksum=<checksum>"))
+        .arg(Arg::with_name("metalink")
+                 .long("metalink")
+                 .short("M")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .help("A Metalink file (.meta4 or .metalink), or its URL, which describes the \
+                        files to download"))
+        .arg(Arg::with_name("metalink_location")
+                 .long("metalink-location")
+                 .takes_value(true)
+                 .help("The country codes of the preferred mirrors of the Metalink files, \
+                        separated by commas (e.g. fr,de)"))
+        .arg(Arg::with_name("mirror")
+                 .long("mirror")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .help("Another URL which provides the same remote content (only with one URL)"))
+        .arg(Arg::with_name("mirrors_file")
+                 .long("mirrors-file")
+                 .takes_value(true)
+                 .help("A file which contains other URLs which provide the same remote content, \
+                        one per line (only with one URL)"))
         .arg(Arg::with_name("debug")
                  .long("debug")
                  .short("d")
                  .help("Active the debug mode"))
+        .arg(Arg::with_name("user")
+                 .long("user")
+                 .short("u")
+                 .takes_value(true)
+                 .help("The credentials to access the remote content, as user[:password] \
+                        (default: from $SNATCH_USERNAME and $SNATCH_PASSWORD, ~/.netrc, or \
+                        asked)"))
+        .arg(Arg::with_name("bearer")
+                 .long("bearer")
+                 .takes_value(true)
+                 .conflicts_with("auth_header")
+                 .help("The Bearer token to access the remote content (default: from \
+                        $SNATCH_BEARER_TOKEN)"))
+        .arg(Arg::with_name("auth_header")
+                 .long("auth-header")
+                 .takes_value(true)
+                 .help("A raw Authorization header value to send with every request"))
+        .arg(Arg::with_name("checksum")
+                 .long("checksum")
+                 .takes_value(true)
+                 .help("The expected checksum of the remote content, as algorithm:digest or \
+                        algorithm:<SHA256SUMS-style file or URL> (algorithms: md5, sha1, \
+                        sha256, sha512, blake3)"))
         .arg(Arg::with_name("force")
                  .long("force")
                  .help("Assume Yes to all queries and do not prompt"))
+        .arg(Arg::with_name("max_attempts")
+                 .long("max-attempts")
+                 .takes_value(true)
+                 .help("Maximum number of attempts to download a chunk (default: 5)"))
+        .arg(Arg::with_name("retry_delay")
+                 .long("retry-delay")
+                 .takes_value(true)
+                 .help("Delay in milliseconds before the first retry of a chunk, doubled at each \
+                        retry (default: 500)"))
+        .arg(Arg::with_name("retry_max_delay")
+                 .long("retry-max-delay")
+                 .takes_value(true)
+                 .help("Maximum delay in milliseconds between two attempts (default: 30000)"))
+        .arg(Arg::with_name("no_jitter")
+                 .long("no-jitter")
+                 .help("Do not randomize the delay between two attempts"))
+        .arg(Arg::with_name("limit_rate")
+                 .long("limit-rate")
+                 .takes_value(true)
+                 .help("Maximum download rate of all the connections together, in bytes per \
+                        second (e.g. 500K or 5M)"))
+        .arg(Arg::with_name("limit_connection_rate")
+                 .long("limit-connection-rate")
+                 .takes_value(true)
+                 .help("Maximum download rate of each connection, in bytes per second (e.g. \
+                        500K or 5M)"))
+        .arg(Arg::with_name("connect_timeout")
+                 .long("connect-timeout")
+                 .takes_value(true)
+                 .help("Maximum time to connect to a remote server, in seconds or using a unit \
+                        (e.g. 500ms or 1m), 0 to wait forever (default: 30s)"))
+        .arg(Arg::with_name("read_timeout")
+                 .long("read-timeout")
+                 .takes_value(true)
+                 .help("Maximum time to wait for some bytes from a remote server, in seconds or \
+                        using a unit, 0 to wait forever (default: 60s)"))
+        .arg(Arg::with_name("min_speed")
+                 .long("min-speed")
+                 .takes_value(true)
+                 .help("Minimum download rate of each connection, in bytes per second (e.g. \
+                        10K): a slower connection is aborted and retried"))
+        .arg(Arg::with_name("min_speed_time")
+                 .long("min-speed-time")
+                 .takes_value(true)
+                 .requires("min_speed")
+                 .help("How long a connection may stay under the minimum download rate, in \
+                        seconds or using a unit (default: 30s)"))
+        .arg(Arg::with_name("max_redirects")
+                 .long("max-redirects")
+                 .takes_value(true)
+                 .help("Maximum number of redirections to follow, 0 to follow none (default: \
+                        10)"))
+        .arg(Arg::with_name("allow_insecure_redirects")
+                 .long("allow-insecure-redirects")
+                 .help("Follow the redirections from HTTPS to HTTP"))
+        .arg(Arg::with_name("location_trusted")
+                 .long("location-trusted")
+                 .help("Send the credentials to the other hosts the remote content is \
+                        redirected to"))
+        .arg(Arg::with_name("no_write_buffer")
+                 .long("no-write-buffer")
+                 .help("Write the received bytes to the local file at once, instead of \
+                        gathering them in larger blocks"))
+        .arg(Arg::with_name("storage")
+                 .long("storage")
+                 .takes_value(true)
+                 .help("How to store the remote content while downloading it: file, mmap (a \
+                        memory-mapped file, whose blocks are always reserved) or parts (a part \
+                        file for each chunk, concatenated at the end) (default: file)"))
+        .arg(Arg::with_name("prealloc")
+                 .long("prealloc")
+                 .takes_value(true)
+                 .help("How to allocate the local file before downloading: none (it grows with \
+                        the received bytes), sparse (it gets its final length at once) or full \
+                        (its blocks are reserved at once) (default: sparse)"))
         .arg(Arg::with_name("url")
             .index(1)
-            //.multiple(true)
-            .required(true))
+            .multiple(true)
+            .required_unless_one(&["input_file", "metalink"]))
         .get_matches();
 
+    // The standard output only contains the remote content, so the messages go to the standard
+    // error
+    let file = if argparse.is_present("stdout") {
+        Some(STDOUT_FILE)
+    } else {
+        argparse.value_of("file").or_else(|| argparse.value_of("output"))
+    };
+    if file == Some(STDOUT_FILE) {
+        logs::use_stderr();
+    }
+
     // Get informations from arguments
 
-    let url = argparse.value_of("url").unwrap();
+    let mut requests: Vec<DownloadRequest> = argparse
+        .values_of("url")
+        .map(|urls| urls.map(DownloadRequest::new).collect())
+        .unwrap_or_default();
+
+    if let Some(input_file) = argparse.value_of("input_file") {
+        let mut content = String::new();
+        let read_result = if input_file == "-" {
+            io::stdin().read_to_string(&mut content)
+        } else {
+            File::open(input_file).and_then(|mut file| file.read_to_string(&mut content))
+        };
+        if let Err(error) = read_result {
+            error!(&format!("Cannot read the input file {}: {}", input_file, error));
+            exit(1);
+        }
+        match parse_input_list(&content) {
+            Ok(input_requests) => requests.extend(input_requests),
+            Err(error) => {
+                error!(&format!("Cannot read the input file {}: {}", input_file, error));
+                exit(1);
+            }
+        }
+    }
+
+    let metalink_locations: Vec<String> = argparse
+        .value_of("metalink_location")
+        .map(|locations| {
+                 locations
+                     .split(',')
+                     .map(|location| String::from(location.trim()))
+                     .filter(|location| !location.is_empty())
+                     .collect()
+             })
+        .unwrap_or_default();
+    for metalink in argparse.values_of("metalink").into_iter().flatten() {
+        let content = if metalink.starts_with("http://") || metalink.starts_with("https://") {
+            fetch_text(metalink)
+        } else {
+            let mut content = String::new();
+            File::open(metalink)
+                .and_then(|mut file| file.read_to_string(&mut content))
+                .map(|_| content)
+                .map_err(SnatchError::Io)
+        };
+        match content.and_then(|content| parse_metalink(&content)) {
+            Ok(metalink_files) => {
+                for mut metalink_file in metalink_files {
+                    metalink_file.prefer_locations(&metalink_locations);
+                    match metalink_request(metalink_file) {
+                        Ok(request) => requests.push(request),
+                        Err(name) => {
+                            warning!(&format!("Ignoring {} from the Metalink file {}: no HTTP \
+                                               URL to download it",
+                                              name,
+                                              metalink))
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                error!(&format!("Cannot read the Metalink file {}: {}", metalink, error));
+                exit(1);
+            }
+        }
+    }
+
+    if let Some(file) = file {
+        if requests.len() != 1 {
+            error!("The local file can be given only to download a single URL!");
+            exit(1);
+        }
+        requests[0].file = Some(String::from(file));
+    }
+    if requests.iter().any(is_stdout_request) {
+        if requests.len() > 1 {
+            error!("The standard output can be used only to download a single URL!");
+            exit(1);
+        }
+        logs::use_stderr();
+    }
 
-    let file = argparse
-        .value_of("file")
-        .unwrap_or_else(|| url.split('/').last().unwrap_or(DEFAULT_FILENAME));
+    let mut mirror_urls: Vec<String> = argparse
+        .values_of("mirror")
+        .map(|mirrors| mirrors.map(String::from).collect())
+        .unwrap_or_default();
+    if let Some(mirrors_file) = argparse.value_of("mirrors_file") {
+        let mut content = String::new();
+        if let Err(error) = File::open(mirrors_file)
+               .and_then(|mut file| file.read_to_string(&mut content)) {
+            error!(&format!("Cannot read the mirrors file {}: {}", mirrors_file, error));
+            exit(1);
+        }
+        mirror_urls.extend(parse_mirror_list(&content));
+    }
+    if !mirror_urls.is_empty() {
+        if requests.len() != 1 {
+            error!("The mirrors can be given only to download a single URL!");
+            exit(1);
+        }
+        requests[0].mirrors.extend(mirror_urls);
+    }
+    if let Some(checksum) = argparse.value_of("checksum") {
+        for request in &mut requests {
+            if request.checksum.is_none() {
+                request.checksum = Some(String::from(checksum));
+            }
+        }
+    }
 
     // Check if multi-threaded download is possible
-    let mut threads: usize = value_t!(argparse, "threads", usize)
+    let threads: usize = value_t!(argparse, "threads", usize)
         .and_then(|v| if v != 0 {
                       Ok(v)
                   } else {
@@ -67,59 +396,884 @@ fn main() {
                   })
         .unwrap_or(num_cpus::get_physical());
 
+    let jobs: usize = value_t!(argparse, "jobs", usize)
+        .and_then(|v| if v != 0 {
+                      Ok(v)
+                  } else {
+                      Err(clap::Error::with_description("Cannot download 0 file at a time",
+                                                        clap::ErrorKind::InvalidValue))
+                  })
+        .unwrap_or(1);
+
+    let default_retry_policy = RetryPolicy::default();
+    let retry_policy = RetryPolicy {
+        max_attempts: value_t!(argparse, "max_attempts", u32)
+            .and_then(|v| if v != 0 {
+                          Ok(v)
+                      } else {
+                          Err(clap::Error::with_description("Cannot download a chunk using 0 \
+                                                             attempt",
+                                                            clap::ErrorKind::InvalidValue))
+                      })
+            .unwrap_or(default_retry_policy.max_attempts),
+        base_delay: value_t!(argparse, "retry_delay", u64)
+            .map(Duration::from_millis)
+            .unwrap_or(default_retry_policy.base_delay),
+        max_delay: value_t!(argparse, "retry_max_delay", u64)
+            .map(Duration::from_millis)
+            .unwrap_or(default_retry_policy.max_delay),
+        jitter: !argparse.is_present("no_jitter"),
+    };
+
+    let rate_limit = |name: &str| {
+        argparse
+            .value_of(name)
+            .map(|rate| match parse_filesize(rate) {
+                     Some(rate) if rate > 0 => rate,
+                     _ => {
+                         error!(&format!("Invalid download rate: {}", rate));
+                         exit(1);
+                     }
+                 })
+    };
+    let total_rate = rate_limit("limit_rate");
+    let connection_rate = rate_limit("limit_connection_rate");
+
+    let reorder_buffer = match argparse.value_of("reorder_buffer") {
+        Some(size) => {
+            parse_filesize(size).unwrap_or_else(|| {
+                error!(&format!("Invalid reorder buffer size: {}", size));
+                exit(1);
+            }) as usize
+        }
+        None => DEFAULT_REORDER_BUFFER_BYTES,
+    };
+
+    let duration = |name: &str, default_secs: u64| {
+        match argparse.value_of(name) {
+            Some(duration) => {
+                parse_duration(duration).unwrap_or_else(|| {
+                    error!(&format!("Invalid duration: {}", duration));
+                    exit(1);
+                })
+            }
+            None => Duration::from_secs(default_secs),
+        }
+    };
+    // A null timeout disables it
+    let timeout = |name: &str, default_secs: u64| {
+        Some(duration(name, default_secs)).filter(|timeout| *timeout > Duration::from_secs(0))
+    };
+    let timeouts = Timeouts {
+        connect: timeout("connect_timeout", DEFAULT_CONNECT_TIMEOUT_SECS),
+        read: timeout("read_timeout", DEFAULT_READ_TIMEOUT_SECS),
+        min_speed: rate_limit("min_speed").map(|speed| {
+            MinSpeed {
+                speed,
+                time: duration("min_speed_time", DEFAULT_MIN_SPEED_SECS),
+            }
+        }),
+    };
+    if let Some(MinSpeed { time, .. }) = timeouts.min_speed {
+        if time == Duration::from_secs(0) {
+            error!("The minimum download rate needs a non null duration");
+            exit(1);
+        }
+    }
+
+    let redirect_policy = RedirectPolicy {
+        max_redirects: value_t!(argparse, "max_redirects", u32).unwrap_or(DEFAULT_MAX_REDIRECTS),
+        allow_downgrade: argparse.is_present("allow_insecure_redirects"),
+        forward_credentials: argparse.is_present("location_trusted"),
+    };
+
+    let storage = match argparse.value_of("storage") {
+        Some(storage) => {
+            Storage::from_name(storage).unwrap_or_else(|| {
+                error!(&format!("Invalid storage: {}", storage));
+                exit(1);
+            })
+        }
+        None => Storage::File,
+    };
+
+    let prealloc = match argparse.value_of("prealloc") {
+        Some(prealloc) => {
+            Prealloc::from_name(prealloc).unwrap_or_else(|| {
+                error!(&format!("Invalid preallocation: {}", prealloc));
+                exit(1);
+            })
+        }
+        None => Prealloc::Sparse,
+    };
+
     if argparse.is_present("debug") {
         info!(&format!("version: {}", crate_version!()));
-        info!(&format!("file: {}", file));
+        info!(&format!("downloads: {}", requests.len()));
+        info!(&format!("jobs: {}", jobs));
         info!(&format!("threads: {}", threads));
+        info!(&format!("retry policy: {:?}", retry_policy));
+        if let Some(total_rate) = total_rate {
+            info!(&format!("rate limit: {}/s", format_filesize(total_rate)));
+        }
+        if let Some(connection_rate) = connection_rate {
+            info!(&format!("connection rate limit: {}/s", format_filesize(connection_rate)));
+        }
+        info!(&format!("timeouts: {:?}", timeouts));
+        info!(&format!("redirect policy: {:?}", redirect_policy));
+        info!(&format!("storage: {:?}", storage));
+        info!(&format!("preallocation: {:?}", prealloc));
+    }
+
+    // Look for the credentials in the arguments, then in the environment and the .netrc file,
+    // and finally ask them to the user
+    let credentials = argparse.value_of("user").map(|user| {
+        let mut user_parts = user.splitn(2, ':');
+        let username = user_parts.next().unwrap_or("");
+        match user_parts.next() {
+            Some(password) => Credentials::new(username, Some(password)),
+            None => {
+                let password = prompt_password(&format!("Password for {}:", username))
+                    .unwrap_or_else(|error| {
+                                        error!(&format!("Cannot read the password: {}", error));
+                                        exit(1);
+                                    });
+                Credentials::new(username, Some(&password))
+            }
+        }
+    });
+
+    // The tokens and raw headers are sent from the first request
+    let auth_header = if let Some(header_value) = argparse.value_of("auth_header") {
+        Some(AuthorizationHeaderFactory::custom(String::from(header_value)))
+    } else {
+        argparse
+            .value_of("bearer")
+            .map(String::from)
+            .or_else(|| env::var(DEFAULT_TOKEN_VAR).ok())
+            .map(AuthorizationHeaderFactory::bearer)
+    };
+
+    let options = Arc::new(DownloadOptions {
+                               threads,
+                               connection: ConnectionSettings {
+                                   retry_policy,
+                                   throttle: Throttle::new(total_rate, connection_rate),
+                                   timeouts,
+                               },
+                               redirect_policy,
+                               write_buffer: if argparse.is_present("no_write_buffer") {
+                                   0
+                               } else {
+                                   WRITE_BUFFER_BYTES
+                               },
+                               storage,
+                               prealloc,
+                               reorder_buffer,
+                               credentials,
+                               auth_header,
+                               force: argparse.is_present("force"),
+                               debug: argparse.is_present("debug"),
+                               prepare_lock: Mutex::new(()),
+                           });
+
+    // Download the files using a fixed pool of jobs
+    let requests = Arc::new(requests);
+    let next_request = Arc::new(AtomicUsize::new(0));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let mut job_handles = vec![];
+
+    for _ in 0..jobs {
+        let requests = requests.clone();
+        let next_request = next_request.clone();
+        let results = results.clone();
+        let options = options.clone();
+
+        job_handles.push(thread::spawn(move || loop {
+            let request_index = next_request.fetch_add(1, Ordering::SeqCst);
+            if request_index >= requests.len() {
+                break;
+            }
+            let result = download(&requests[request_index], &options);
+            results.lock().unwrap().push((request_index, result));
+        }));
     }
 
+    for job_handle in job_handles {
+        if job_handle.join().is_err() {
+            error!("A download has stopped unexpectedly!");
+            exit(1);
+        }
+    }
+
+    let mut results = results.lock().unwrap();
+    results.sort_by_key(|&(request_index, _)| request_index);
+
+    if requests.len() > 1 {
+        print_summary(&requests, &results);
+    }
+
+    let exit_code = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().err())
+        .map(|error| match *error {
+                 SnatchError::ChecksumMismatch { .. } => CHECKSUM_MISMATCH_EXIT_CODE,
+                 _ => 1,
+             })
+        .max();
+    if let Some(exit_code) = exit_code {
+        exit(exit_code);
+    }
+
+}
+
+/// Function to get the local file to save a remote content: the file of its download request,
+/// else the file name given by the remote server, else the file name of its URL
+fn get_local_file(request: &DownloadRequest, remote_file_name: Option<&str>) -> String {
+    request
+        .file
+        .clone()
+        .or_else(|| remote_file_name.map(String::from))
+        .or_else(|| url_file_name(&request.url))
+        .unwrap_or_else(|| String::from(DEFAULT_FILENAME))
+}
+
+/// Function to get the download request of a file described by a Metalink file: the URL which
+/// has the best priority, and the others as mirrors.
+/// This function returns the name of the file if there is no URL to download it.
+fn metalink_request(metalink_file: MetalinkFile) -> Result<DownloadRequest, String> {
+    let mut urls = metalink_file.urls.into_iter().map(|metalink_url| metalink_url.url);
+    let url = match urls.next() {
+        Some(url) => url,
+        None => return Err(metalink_file.name),
+    };
+    let mut request = DownloadRequest::new(&url);
+    request.file = Some(metalink_file.name);
+    request.mirrors = urls.collect();
+    request.size = metalink_file.size;
+    request.checksums = metalink_file.checksums;
+    request.pieces = metalink_file.pieces;
+    Ok(request)
+}
+
+/// Function to download a single remote content, starting again from scratch each time the
+/// remote content changes while downloading it
+fn download(request: &DownloadRequest,
+            options: &DownloadOptions)
+            -> Result<DownloadStatus, SnatchError> {
+    let mut content_changes = 0;
+    loop {
+        match try_download(request, options) {
+            Err(SnatchError::ContentChanged) if is_stdout_request(request) => {
+                // The bytes already written to the standard output cannot be written again
+                error!("Download failed: the remote content has changed while writing it to the \
+                        standard output!");
+                return Err(SnatchError::ContentChanged);
+            }
+            Err(SnatchError::ContentChanged) => {
+                content_changes += 1;
+                if content_changes > MAX_CONTENT_CHANGES {
+                    error!("Download failed: the remote content keeps changing while \
+                            downloading it!");
+                    return Err(SnatchError::ContentChanged);
+                }
+                // The download state does not match the new remote content anymore
+                warning!("The remote content has changed while downloading it! \
+                          Downloading it again.");
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Function to download a single remote content, and to check it once downloaded
+fn try_download(request: &DownloadRequest,
+                options: &DownloadOptions)
+                -> Result<DownloadStatus, SnatchError> {
+    let url = &request.url;
+    if options.debug {
+        info!(&format!("url: {}", url));
+    }
+
+    let prepare_guard = options.prepare_lock.lock().unwrap();
+
+    let mut credential_providers: Vec<Box<dyn CredentialProvider>> = vec![];
+    if let Some(ref credentials) = options.credentials {
+        credential_providers.push(Box::new(FixedCredentials(credentials.clone())));
+    }
+    credential_providers.push(Box::new(EnvCredentials::default()));
+    credential_providers.push(Box::new(NetrcCredentials::default()));
+    credential_providers.push(Box::new(PromptCredentials));
+    let credential_provider = CredentialChain(credential_providers);
+
+    let cargo_info = match get_cargo_info(url,
+                                          &credential_provider,
+                                          options.auth_header.clone(),
+                                          &options.connection.timeouts,
+                                          &options.redirect_policy) {
+        Ok(cargo_info) => cargo_info,
+        Err(error) => {
+            error!(&format!("Cannot get the remote content informations: {}", error));
+            return Err(error);
+        }
+    };
+    if options.debug {
+        for (redirect, target) in cargo_info
+                .redirects
+                .iter()
+                .zip(cargo_info.redirects.iter().skip(1).chain(Some(&cargo_info.url))) {
+            info!(&format!("redirect: {} -> {}", redirect, target));
+        }
+        info!(&format!("final url: {}", cargo_info.url));
+    }
+    if let Some(content_length) = cargo_info.content_length {
+        info!(&format!("# Remote content length: {}", format_filesize(content_length)));
+    }
+    if let (Some(size), Some(content_length)) = (request.size, cargo_info.content_length) {
+        if size != content_length {
+            let error = SnatchError::LengthMismatch {
+                expected: size,
+                received: content_length,
+            };
+            error!(&format!("The remote content is not the expected one: {}", error));
+            return Err(error);
+        }
+    }
+
+    // The remote server may give the name of the file
+    let file = get_local_file(request, cargo_info.file_name.as_deref());
+    let to_stdout = file == STDOUT_FILE;
     let local_path = Path::new(&file);
+    let state_path = DownloadState::state_path(local_path);
+    let part_path = DownloadState::part_path(local_path);
+    if options.debug {
+        info!(&format!("file: {}", file));
+    }
 
-    if local_path.exists() {
-        if local_path.is_dir() {
-            epanic!("The local path to store the remote content is already exists, \
-                        and is a directory!");
+    // Get the expected checksum before downloading anything, so a wrong checksum fails fast
+    let checksum = match request.checksum {
+        Some(ref argument) => {
+            // The file name of the remote content is looked up when writing to the standard
+            // output
+            let remote_file = get_local_file(&DownloadRequest::new(url),
+                                             cargo_info.file_name.as_deref());
+            let file_name = if to_stdout {
+                &remote_file
+            } else {
+                local_path
+                    .file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .unwrap_or(&file)
+            };
+            match resolve_checksum(argument, file_name) {
+                Ok(checksum) => Some(checksum),
+                Err(error) => {
+                    error!(&format!("Cannot get the expected checksum: {}", error));
+                    return Err(error);
+                }
+            }
         }
-        if !argparse.is_present("force") {
-            let user_input = prompt_user("The path to store the file already exists! \
-                                          Do you want to override it? [y/N]");
+        None => None,
+    };
+    // A previous download can be resumed only if its partial file and its state file have been
+    // kept
+    let resumable = !to_stdout && part_path.is_file() && state_path.is_file();
+
+    if !to_stdout && local_path.is_dir() {
+        error!("The local path to store the remote content is already exists, \
+                and is a directory!");
+        return Err(SnatchError::Io(io::Error::new(io::ErrorKind::AlreadyExists,
+                                                  "the local path is a directory")));
+    }
+    if resumable {
+        warning!(&format!("A previous download state has been found in {}! \
+                           Trying to resume the download.",
+                          state_path.display()));
+    } else if !to_stdout && local_path.exists() {
+        // The existing file is kept until the remote content is completely downloaded
+        if !options.force {
+            let user_input = prompt_user(&format!("The path to store the file {} already \
+                                                   exists! Do you want to override it? [y/N]",
+                                                  file))
+                    .unwrap_or_default();
             if !(user_input == "y" || user_input == "Y") {
-                exit(0);
+                return Ok(DownloadStatus::Skipped(file));
             }
         } else {
             warning!("The path to store the file already exists! \
-                                 It is going to be overriden.");
+                      It is going to be overriden once the download succeeds.");
         }
     }
 
-    let cargo_info = get_cargo_info(&url).expect("fail to parse url");
-    info!(&format!("# Remote content length: {}",
-                   format_filesize(cargo_info.content_length)));
+    let mirrors = probe_mirrors(&cargo_info,
+                                &request.mirrors,
+                                &credential_provider,
+                                options.auth_header.clone(),
+                                &options.connection.timeouts,
+                                &options.redirect_policy);
+    if !request.mirrors.is_empty() {
+        info!(&format!("# Mirrors: {}", mirrors.mirrors().len()));
+    }
 
-    let local_file = File::create(local_path).expect("[ERROR] Cannot create a file !");
+    // Check the downloaded file against the given checksums, and the checksums sent by the server
+    let mut checksums: Vec<_> = checksum.into_iter().collect();
+    checksums.extend(request.checksums.iter().cloned());
+    checksums.extend(cargo_info.checksums.iter().cloned());
 
-    local_file
-        .set_len(cargo_info.content_length)
-        .expect("Cannot extend file to download size!");
-    let out_file = OutputFileWriter::new(local_file);
+    if to_stdout {
+        drop(prepare_guard);
+        return download_to_stdout(url, cargo_info, &mirrors, &checksums, options);
+    }
+
+    let content_length = match cargo_info.content_length {
+        Some(content_length) => content_length,
+        None => {
+            // The remote content is streamed into a new partial file, which cannot be resumed
+            warning!("The remote content length is unknown! \
+                      Downloading the remote file using one connection.");
+            // A memory-mapped file cannot grow with the received bytes
+            let storage = match options.storage {
+                Storage::Mmap => Storage::File,
+                storage => storage,
+            };
+            let sink = create_sink(storage, Prealloc::None, &part_path, &[], 0)?;
+            let out_file = OutputFileWriter::with_sink(sink)
+                .with_write_buffer(options.write_buffer);
+            if state_path.is_file() && remove_file(&state_path).is_err() {
+                warning!("Cannot remove the previous download state file!");
+            }
+            drop(prepare_guard);
+
+            let result = download_stream(out_file.clone(), &mirrors, &options.connection)
+                    .and_then(|received| match request.size {
+                                  Some(size) if size != received => {
+                                      Err(SnatchError::LengthMismatch {
+                                              expected: size,
+                                              received,
+                                          })
+                                  }
+                                  _ => Ok(received),
+                              });
+            return match result {
+                       Ok(received) => {
+                           finalize_download(&out_file, &part_path, local_path, &checksums)?;
+                           Ok(DownloadStatus::Done(file, received))
+                       }
+                       Err(error) => {
+                           error!(&format!("Download failed: {}! Erasing file... ", error));
+                           if remove_partial_download(&part_path).is_err() {
+                               error!("Cannot remove downloaded file!");
+                           }
+                           Err(error)
+                       }
+                   };
+        }
+    };
 
     // If the server does not accept PartialContent status, download the remote file
     // using only one thread
+    let mut threads = options.threads;
     if !cargo_info.accept_partialcontent {
         warning!("The remote server does not accept PartialContent status! \
                              Downloading the remote file using one thread.");
         threads = 1;
     }
 
-    if download_chunks(cargo_info, out_file, threads as u64, &url) {
-        ok!(&format!("Your download is available in {}",
-                     local_path.to_str().unwrap()));
+    // Load the state of the previous download, if it belongs to the same remote content
+    let previous_state = if resumable && cargo_info.accept_partialcontent {
+        match DownloadState::load(&state_path) {
+            Ok(ref state) if !state.matches(url, &cargo_info) => {
+                warning!("The remote content has changed since the previous download! \
+                          Downloading it again from scratch.");
+                None
+            }
+            Ok(state) => Some(state),
+            Err(_) => {
+                warning!("Cannot read the previous download state! \
+                          Downloading the remote content again from scratch.");
+                None
+            }
+        }
     } else {
-        // If the file is not ok, delete it from the file system
-        error!("Download failed! An error occured - erasing file... ");
-        if remove_file(local_path).is_err() {
-            error!("Cannot remove downloaded file!");
+        None
+    };
+
+    // The partial file may have been written using another storage
+    let previous_download = match previous_state {
+        Some(state) => {
+            match open_sink(options.storage, &part_path, &state.chunks, content_length) {
+                Ok(sink) => Some((sink, state)),
+                Err(_) => {
+                    warning!("Cannot open the partial file of the previous download! \
+                              Downloading the remote content again from scratch.");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Fail now rather than in the middle of the download
+    let needed = match previous_download {
+        Some((_, ref state)) => content_length.saturating_sub(state.downloaded_bytes()),
+        None => content_length,
+    };
+    // The part files are copied into the partial file at the end
+    let needed = match options.storage {
+        Storage::PartFiles => needed + content_length,
+        _ => needed,
+    };
+    if let Err(error) = check_available_space(&part_path, needed) {
+        error!(&format!("{}", error));
+        return Err(error);
+    }
+
+    let (sink, download_state) = match previous_download {
+        Some((sink, state)) => {
+            ok!(&format!("Resuming the download, {} already downloaded",
+                         format_filesize(state.downloaded_bytes())));
+            (sink, state)
+        }
+        None => {
+            let nb_chunks = if cargo_info.accept_partialcontent {
+                threads as u64 * CHUNKS_PER_WORKER
+            } else {
+                1
+            };
+            let state = DownloadState::new(url, &cargo_info, nb_chunks);
+            let sink = create_sink(options.storage,
+                                   options.prealloc,
+                                   &part_path,
+                                   &state.chunks,
+                                   content_length)?;
+            (sink, state)
         }
+    };
+
+    let out_file = OutputFileWriter::with_sink(sink).with_write_buffer(options.write_buffer);
+    let state_tracker = StateTracker::new(download_state, state_path);
+    if state_tracker.save().is_err() {
+        warning!("Cannot save the download state file! The download will not be resumable.");
     }
+    drop(prepare_guard);
+
+    // Check each piece as soon as it is downloaded, if their checksums are known
+    let piece_verifier = match request.pieces {
+        Some(ref pieces) if pieces.matches(content_length) => {
+            Some(PieceVerifier::new(pieces.clone(),
+                                    content_length,
+                                    options.connection.retry_policy.max_attempts))
+        }
+        Some(_) => {
+            warning!("The pieces checksums do not match the remote content length! \
+                      They are not going to be verified.");
+            None
+        }
+        None => None,
+    };
+
+    let accept_partialcontent = cargo_info.accept_partialcontent;
 
+    match download_chunks(cargo_info,
+                          out_file.clone(),
+                          state_tracker.clone(),
+                          &mirrors,
+                          threads as u64,
+                          piece_verifier,
+                          &options.connection) {
+        Ok(()) => {
+            let result = finalize_download(&out_file, &part_path, local_path, &checksums);
+            // The state file is kept only if the partial file can still be renamed
+            match result {
+                Err(SnatchError::Io(_)) => {}
+                _ => {
+                    if state_tracker.remove().is_err() {
+                        warning!("Cannot remove the download state file!");
+                    }
+                }
+            }
+            result?;
+            Ok(DownloadStatus::Done(file, content_length))
+        }
+        // The download is started again by the caller
+        Err(SnatchError::ContentChanged) => Err(SnatchError::ContentChanged),
+        Err(error) => {
+            if accept_partialcontent {
+                // Keep the partial file and its state, to resume the download later
+                error!(&format!("Download failed: {}! Run the same command again to resume it.",
+                                error));
+            } else {
+                // If the file is not ok, and cannot be resumed, delete it from the file system
+                error!(&format!("Download failed: {}! Erasing file... ", error));
+                if remove_partial_download(&part_path).is_err() {
+                    error!("Cannot remove downloaded file!");
+                }
+                if state_tracker.remove().is_err() {
+                    error!("Cannot remove the download state file!");
+                }
+            }
+            Err(error)
+        }
+    }
+}
+
+/// Function to create the local file to save a remote content, and its directories.
+/// The file is opened for reading too, to check its pieces.
+fn create_local_file(local_path: &Path) -> io::Result<File> {
+    // The directories of the local file may be given by a Metalink file
+    if let Some(parent) = local_path.parent() {
+        if !parent.as_os_str().is_empty() {
+            create_dir_all(parent)?;
+        }
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(local_path)
+}
+
+/// Function to download a remote content to the standard output.
+/// Its chunks are still downloaded in parallel, and written in order through a reorder buffer.
+/// Such a download cannot be resumed, and its pieces cannot be checked: only its checksums are
+/// checked, once it has been written.
+fn download_to_stdout(url: &str,
+                      cargo_info: CargoInfo,
+                      mirrors: &MirrorPool,
+                      checksums: &[Checksum],
+                      options: &DownloadOptions)
+                      -> Result<DownloadStatus, SnatchError> {
+    let sink = Arc::new(OrderedSink::new(io::stdout(), options.reorder_buffer)
+                            .with_checksums(checksums));
+    let out_file = OutputFileWriter::with_sink(sink.clone())
+        .with_write_buffer(options.write_buffer);
+
+    let result = match cargo_info.content_length {
+        Some(content_length) => {
+            let (threads, nb_chunks) = if cargo_info.accept_partialcontent {
+                (options.threads as u64, options.threads as u64 * CHUNKS_PER_WORKER)
+            } else {
+                (1, 1)
+            };
+            let state_tracker =
+                StateTracker::in_memory(DownloadState::new(url, &cargo_info, nb_chunks));
+            out_file
+                .set_len(content_length)
+                .and_then(|_| {
+                              download_chunks(cargo_info,
+                                              out_file.clone(),
+                                              state_tracker,
+                                              mirrors,
+                                              threads,
+                                              None,
+                                              &options.connection)
+                          })
+                .map(|_| content_length)
+        }
+        None => {
+            warning!("The remote content length is unknown! \
+                      Downloading the remote file using one connection.");
+            download_stream(out_file.clone(), mirrors, &options.connection)
+        }
+    };
+
+    match result.and_then(|received| out_file.finish().map(|_| received)) {
+        Ok(received) => {
+            if !checksums.is_empty() {
+                if let Err(error) = sink.verify() {
+                    error!(&format!("Checksum verification failed: {}!", error));
+                    return Err(error);
+                }
+                for checksum in checksums {
+                    ok!(&format!("Checksum verified ({})", checksum));
+                }
+            }
+            ok!("The remote content has been written to the standard output");
+            Ok(DownloadStatus::Done(String::from(STDOUT_FILE), received))
+        }
+        Err(error) => {
+            error!(&format!("Download failed: {}!", error));
+            Err(error)
+        }
+    }
+}
+
+/// Function to check if a download request writes to the standard output
+fn is_stdout_request(request: &DownloadRequest) -> bool {
+    request.file.as_deref() == Some(STDOUT_FILE)
+}
+
+/// Function to check that the file system of the partial file has `needed` bytes of free space.
+/// Nothing is checked if the platform does not tell the free space.
+fn check_available_space(part_path: &Path, needed: Bytes) -> Result<(), SnatchError> {
+    // The directory of the partial file may not be created yet
+    let mut directory = part_path.parent();
+    while let Some(path) = directory {
+        if path.is_dir() {
+            break;
+        }
+        directory = path.parent();
+    }
+    let directory = match directory {
+        Some(path) if path.as_os_str().is_empty() => Path::new("."),
+        Some(path) => path,
+        None => Path::new("."),
+    };
+    match available_space(directory) {
+        Ok(Some(available)) if available < needed => {
+            Err(SnatchError::NotEnoughSpace {
+                    needed,
+                    available,
+                })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Function to create the storage of a new download in the partial file, with the given length,
+/// allocated as asked.
+/// The part files are created for the given chunks.
+fn create_sink(storage: Storage,
+               prealloc: Prealloc,
+               part_path: &Path,
+               chunks: &[ChunkState],
+               content_length: Bytes)
+               -> Result<Arc<dyn ChunkSink>, SnatchError> {
+    let local_file = create_local_file(part_path)?;
+    PartFilesSink::remove(part_path)?;
+    let sink: Arc<dyn ChunkSink> = match storage {
+        Storage::File => Arc::new(FileSink::new(local_file)),
+        Storage::Mmap => Arc::new(MmapSink::new(local_file)?),
+        Storage::PartFiles => Arc::new(PartFilesSink::create(part_path, chunks)?),
+    };
+    // A memory-mapped file cannot grow with the received bytes, and its blocks are reserved as
+    // soon as its length is set
+    let prealloc = match (storage, prealloc) {
+        (Storage::Mmap, Prealloc::None) => Prealloc::Sparse,
+        (_, prealloc) => prealloc,
+    };
+    match prealloc {
+        Prealloc::None => {}
+        Prealloc::Sparse => sink.set_len(content_length)?,
+        Prealloc::Full => {
+            sink.set_len(content_length)?;
+            sink.preallocate(content_length)?;
+        }
+    }
+    Ok(sink)
+}
+
+/// Function to open the storage of a previous download, to resume it, with the chunks of its
+/// state.
+/// This function returns an error if the previous download used another storage.
+/// The partial file may be shorter than the remote content, if it was not preallocated.
+fn open_sink(storage: Storage,
+             part_path: &Path,
+             chunks: &[ChunkState],
+             content_length: Bytes)
+             -> Result<Arc<dyn ChunkSink>, SnatchError> {
+    if storage == Storage::PartFiles {
+        let sink = PartFilesSink::open(part_path, chunks)?;
+        sink.set_len(content_length)?;
+        return Ok(Arc::new(sink));
+    }
+
+    // The partial file written by the part files is empty until the end of the download
+    if PartFilesSink::exist(part_path) {
+        return Err(SnatchError::Io(io::Error::new(io::ErrorKind::InvalidData,
+                                                  "the partial file is written by part files")));
+    }
+    let local_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(part_path)?;
+    let length = local_file.metadata()?.len();
+    if length > content_length {
+        return Err(SnatchError::Io(io::Error::new(io::ErrorKind::InvalidData,
+                                                  "the partial file has another length")));
+    }
+    match storage {
+        Storage::Mmap => {
+            // A memory-mapped file cannot grow with the received bytes
+            let sink = MmapSink::new(local_file)?;
+            if length < content_length {
+                sink.set_len(content_length)?;
+            }
+            Ok(Arc::new(sink))
+        }
+        _ => Ok(Arc::new(FileSink::new(local_file))),
+    }
+}
+
+/// Function to remove the partial file of a failed download, and its part files if any.
+/// Both are removed even if one of them cannot be, and a missing partial file is not an error.
+fn remove_partial_download(part_path: &Path) -> Result<(), SnatchError> {
+    let file_result = match remove_file(part_path) {
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.map_err(SnatchError::from),
+    };
+    let parts_result = PartFilesSink::remove(part_path);
+    file_result.and(parts_result)
+}
+
+/// Function to finish a download, once the whole remote content is in its partial file: the
+/// partial file is written to the disk, checked against the expected checksums, and then renamed
+/// over the local file at once - so the local file is always either the previous one, or the
+/// complete new one.
+/// A partial file which does not have the expected checksums is removed.
+fn finalize_download(out_file: &OutputFileWriter,
+                     part_path: &Path,
+                     local_path: &Path,
+                     checksums: &[Checksum])
+                     -> Result<(), SnatchError> {
+    out_file.finish()?;
+    if !checksums.is_empty() {
+        info!("Verifying the checksums...");
+        if let Err(error) = verify_file(part_path, checksums) {
+            error!(&format!("Checksum verification failed: {}! Erasing file... ", error));
+            if remove_file(part_path).is_err() {
+                error!("Cannot remove downloaded file!");
+            }
+            return Err(error);
+        }
+        for checksum in checksums {
+            ok!(&format!("Checksum verified ({})", checksum));
+        }
+    }
+
+    rename(part_path, local_path)?;
+    // Write the rename to the disk too, where the platform allows to sync a directory
+    let directory = match local_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
+    }
+
+    ok!(&format!("Your download is available in {}",
+                 local_path.to_str().unwrap()));
+    Ok(())
+}
+
+/// Function to print which downloads succeeded or failed
+fn print_summary(requests: &[DownloadRequest],
+                 results: &[(usize, Result<DownloadStatus, SnatchError>)]) {
+    println!();
+    println!("{:<8} {:>12}  FILE", "STATUS", "SIZE");
+    for &(request_index, ref result) in results {
+        match *result {
+            Ok(DownloadStatus::Done(ref file, content_length)) => {
+                println!("{:<8} {:>12}  {}", "OK", format_filesize(content_length), file)
+            }
+            Ok(DownloadStatus::Skipped(ref file)) => {
+                println!("{:<8} {:>12}  {}", "SKIPPED", "-", file)
+            }
+            Err(ref error) => {
+                let file = get_local_file(&requests[request_index], None);
+                println!("{:<8} {:>12}  {} ({})", "FAILED", "-", file, error)
+            }
+        }
+    }
 }
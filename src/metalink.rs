@@ -0,0 +1,260 @@
+use Bytes;
+use checksum::{Checksum, ChecksumAlgorithm};
+use error::SnatchError;
+use pieces::Pieces;
+use roxmltree::{Document, Node};
+use std::path::{Component, Path};
+
+/// Constant to represent the priority of an URL without any priority - the lowest one
+const DEFAULT_URL_PRIORITY: u32 = 999999;
+
+/// Structure that contains an URL of a Metalink file, with its priority (the lower, the better)
+/// and the country code of its location
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetalinkUrl {
+    pub url: String,
+    pub priority: u32,
+    pub location: Option<String>,
+}
+
+/// Structure that contains a file described by a Metalink (RFC 5854, or the former version 3)
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetalinkFile {
+    /// The relative path of the local file
+    pub name: String,
+    pub size: Option<Bytes>,
+    /// The URLs of the file, sorted by priority
+    pub urls: Vec<MetalinkUrl>,
+    pub checksums: Vec<Checksum>,
+    pub pieces: Option<Pieces>,
+}
+
+impl MetalinkFile {
+    /// Function to sort the URLs by priority, the URLs of the given locations being used first
+    pub fn prefer_locations(&mut self, locations: &[String]) {
+        self.urls
+            .sort_by_key(|url| {
+                             let is_preferred = url.location
+                                 .as_ref()
+                                 .map(|location| {
+                                          locations
+                                              .iter()
+                                              .any(|preferred| {
+                                                       preferred.eq_ignore_ascii_case(location)
+                                                   })
+                                      })
+                                 .unwrap_or(false);
+                             (!is_preferred, url.priority)
+                         });
+    }
+}
+
+/// Function to get the files described by the content of a Metalink file.
+/// Only the HTTP(S) URLs are kept, since they are the only ones which can be downloaded.
+pub fn parse_metalink(content: &str) -> Result<Vec<MetalinkFile>, SnatchError> {
+    let document = Document::parse(content)
+        .map_err(|error| SnatchError::InvalidMetalink(error.to_string()))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "metalink" {
+        return Err(SnatchError::InvalidMetalink(String::from("not a metalink")));
+    }
+
+    root.descendants()
+        .filter(|node| node.is_element() && node.tag_name().name() == "file")
+        .map(parse_file)
+        .collect()
+}
+
+fn parse_file(file_node: Node) -> Result<MetalinkFile, SnatchError> {
+    let name = file_node.attribute("name").unwrap_or("");
+    if !is_safe_name(name) {
+        return Err(SnatchError::InvalidMetalink(format!("unsafe file name {}", name)));
+    }
+
+    let size = match child_text(file_node, "size") {
+        Some(size) => {
+            Some(size.parse::<Bytes>()
+                     .map_err(|_| SnatchError::InvalidMetalink(format!("invalid size {}", size)))?)
+        }
+        None => None,
+    };
+
+    let mut urls: Vec<MetalinkUrl> = elements(file_node, "url")
+        .filter_map(|url_node| {
+            let url = url_node.text().unwrap_or("").trim();
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return None;
+            }
+            // Version 3 uses a preference from 1 to 100, the higher the better
+            let priority = url_node
+                .attribute("priority")
+                .and_then(|priority| priority.parse().ok())
+                .or_else(|| {
+                             url_node
+                                 .attribute("preference")
+                                 .and_then(|preference| preference.parse::<u32>().ok())
+                                 .map(|preference| 101 - preference.min(100))
+                         })
+                .unwrap_or(DEFAULT_URL_PRIORITY);
+            Some(MetalinkUrl {
+                     url: String::from(url),
+                     priority,
+                     location: url_node.attribute("location").map(String::from),
+                 })
+        })
+        .collect();
+    urls.sort_by_key(|url| url.priority);
+
+    // The hashes of the pieces are not the hashes of the whole file
+    let checksums = elements(file_node, "hash")
+        .filter(|hash_node| {
+                    hash_node
+                        .parent_element()
+                        .map(|parent| parent.tag_name().name() != "pieces")
+                        .unwrap_or(true)
+                })
+        .filter_map(|hash_node| parse_hash(hash_node, hash_node.attribute("type")))
+        .collect();
+
+    let pieces = match elements(file_node, "pieces").next() {
+        Some(pieces_node) => Some(parse_pieces(pieces_node)?),
+        None => None,
+    };
+
+    Ok(MetalinkFile {
+           name: String::from(name),
+           size,
+           urls,
+           checksums,
+           pieces,
+       })
+}
+
+fn parse_pieces(pieces_node: Node) -> Result<Pieces, SnatchError> {
+    let length = pieces_node
+        .attribute("length")
+        .and_then(|length| length.parse::<Bytes>().ok())
+        .ok_or_else(|| SnatchError::InvalidMetalink(String::from("invalid pieces length")))?;
+    let hash_type = pieces_node.attribute("type");
+
+    let checksums = pieces_node
+        .children()
+        .filter(|node| node.is_element() && node.tag_name().name() == "hash")
+        .map(|hash_node| {
+                 parse_hash(hash_node, hash_type).ok_or_else(|| {
+                     SnatchError::InvalidMetalink(String::from("invalid piece hash"))
+                 })
+             })
+        .collect::<Result<Vec<Checksum>, SnatchError>>()?;
+
+    Ok(Pieces {
+           length,
+           checksums,
+       })
+}
+
+/// Function to get the checksum of a hash element, given the name of its algorithm
+/// (e.g. `sha-256`), or None if this algorithm is not supported
+fn parse_hash(hash_node: Node, hash_type: Option<&str>) -> Option<Checksum> {
+    hash_type
+        .and_then(ChecksumAlgorithm::from_name)
+        .and_then(|algorithm| Checksum::from_hex(algorithm, hash_node.text().unwrap_or("").trim()))
+}
+
+/// Function to get the descendant elements of a node which have the given name
+fn elements<'a, 'input>(node: Node<'a, 'input>,
+                        name: &'static str)
+                        -> Box<dyn Iterator<Item = Node<'a, 'input>> + 'a> {
+    Box::new(node.descendants()
+                 .filter(move |child| child.is_element() && child.tag_name().name() == name))
+}
+
+fn child_text<'a>(node: Node<'a, 'a>, name: &'static str) -> Option<&'a str> {
+    elements(node, name)
+        .next()
+        .and_then(|child| child.text())
+        .map(|text| text.trim())
+}
+
+/// Function to check that the name of a file stays in the current directory
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty() &&
+    Path::new(name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod test_metalink {
+
+    use checksum::ChecksumAlgorithm;
+    use super::parse_metalink;
+
+    const METALINK: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="snatch-0.1.3.tar.gz">
+    <size>300000</size>
+    <hash type="sha-256">f0ad929cd259957e160ea442eb80986b5f01dc3b3f8f6e8b8fb2e1c3d6c9f1a2</hash>
+    <pieces length="262144" type="sha-1">
+      <hash>a9993e364706816aba3e25717850c26c9cd0d89d</hash>
+      <hash>84983e441c3bd26ebaae4aa1f95129e5e54670f1</hash>
+    </pieces>
+    <url location="de" priority="2">http://de.example.com/snatch-0.1.3.tar.gz</url>
+    <url location="fr" priority="1">https://fr.example.com/snatch-0.1.3.tar.gz</url>
+    <url priority="1">ftp://ftp.example.com/snatch-0.1.3.tar.gz</url>
+    <metaurl mediatype="torrent">http://example.com/snatch.torrent</metaurl>
+  </file>
+</metalink>"#;
+
+    #[test]
+    fn metalink_should_give_its_files() {
+        let files = parse_metalink(METALINK).unwrap();
+        assert_eq!(1, files.len());
+
+        let file = &files[0];
+        assert_eq!("snatch-0.1.3.tar.gz", file.name);
+        assert_eq!(Some(300000), file.size);
+        assert_eq!(vec!["https://fr.example.com/snatch-0.1.3.tar.gz",
+                        "http://de.example.com/snatch-0.1.3.tar.gz"],
+                   file.urls.iter().map(|url| url.url.as_str()).collect::<Vec<&str>>());
+        assert_eq!(vec![ChecksumAlgorithm::Sha256],
+                   file.checksums.iter().map(|checksum| checksum.algorithm).collect::<Vec<_>>());
+
+        let pieces = file.pieces.as_ref().unwrap();
+        assert_eq!(262144, pieces.length);
+        assert_eq!(2, pieces.checksums.len());
+        assert!(pieces.matches(300000));
+    }
+
+    #[test]
+    fn preferred_locations_should_be_used_first() {
+        let mut file = parse_metalink(METALINK).unwrap().remove(0);
+        file.prefer_locations(&[String::from("DE")]);
+        assert_eq!(Some("de".to_string()), file.urls[0].location);
+    }
+
+    #[test]
+    fn metalink_version_3_should_be_supported() {
+        let content = r#"<metalink version="3.0" xmlns="http://www.metalinker.org/">
+  <files><file name="file.iso">
+    <verification><hash type="md5">900150983cd24fb0d6963f7d28e17f72</hash></verification>
+    <resources>
+      <url type="http" preference="10">http://slow.example.com/file.iso</url>
+      <url type="http" preference="100">http://fast.example.com/file.iso</url>
+    </resources>
+  </file></files>
+</metalink>"#;
+        let file = parse_metalink(content).unwrap().remove(0);
+        assert_eq!("http://fast.example.com/file.iso", file.urls[0].url);
+        assert_eq!(ChecksumAlgorithm::Md5, file.checksums[0].algorithm);
+    }
+
+    #[test]
+    fn unsafe_file_name_should_be_an_error() {
+        assert!(parse_metalink(r#"<metalink><file name="../.bashrc"></file></metalink>"#)
+                    .is_err());
+        assert!(parse_metalink(r#"<metalink><file name="/etc/passwd"></file></metalink>"#)
+                    .is_err());
+    }
+
+}
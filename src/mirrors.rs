@@ -0,0 +1,415 @@
+use authorization::AuthorizationHeaderFactory;
+use Bytes;
+use cargo_helper::{CargoInfo, get_cargo_info, get_raw_header};
+use credentials::CredentialProvider;
+use error::SnatchError;
+use hyper::header::Headers;
+use redirect::RedirectPolicy;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use timeouts::Timeouts;
+
+/// Constant to represent the number of failed requests after which a mirror is not used anymore
+const MAX_MIRROR_FAILURES: u32 = 3;
+
+/// Constant to represent how many times slower than the fastest mirror a mirror must be to be
+/// demoted
+const SLOW_MIRROR_RATIO: f64 = 4.0;
+
+/// Constant to represent the minimal transfer duration (in milliseconds) of a mirror, before its
+/// speed is compared to the other mirrors
+const MIN_SPEED_MEASURE_MILLIS: u64 = 1000;
+
+/// Structure that contains a server which provides the remote content, with the validators of
+/// the remote content on this server
+#[derive(Clone)]
+pub struct Mirror {
+    pub url: String,
+    pub auth_header: Option<AuthorizationHeaderFactory>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Mirror {
+    /// Function to get the value of the `If-Range` header, so the mirror sends the requested
+    /// range only if the remote content has not changed: its entity tag if it is a strong one,
+    /// else its modification date
+    pub fn if_range(&self) -> Option<&str> {
+        self.etag
+            .as_ref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_ref())
+            .map(|validator| validator.as_str())
+    }
+
+    /// Check if the headers of a response describe the same remote content as this mirror,
+    /// comparing the validators they both contain
+    pub fn has_same_content(&self, headers: &Headers) -> bool {
+        let same_etag = match (&self.etag, get_raw_header(headers, "ETag")) {
+            (Some(etag), Some(ref response_etag)) => etag == response_etag,
+            _ => true,
+        };
+        let same_last_modified = match (&self.last_modified,
+                                        get_raw_header(headers, "Last-Modified")) {
+            (Some(last_modified), Some(ref response_last_modified)) => {
+                last_modified == response_last_modified
+            }
+            _ => true,
+        };
+        same_etag && same_last_modified
+    }
+}
+
+/// Structure that contains what is known about the health of a mirror, while downloading
+#[derive(Default)]
+struct MirrorHealth {
+    /// Number of workers which are downloading from this mirror
+    active: usize,
+    failures: u32,
+    /// Number of bytes received from this mirror, and the time spent to receive them
+    transferred_bytes: Bytes,
+    transfer_duration: Duration,
+    demoted: bool,
+}
+
+impl MirrorHealth {
+    /// Function to get the average speed of the connections to this mirror, in bytes per second,
+    /// or None if the mirror has not been used long enough to know it
+    fn speed(&self) -> Option<f64> {
+        if self.transfer_duration < Duration::from_millis(MIN_SPEED_MEASURE_MILLIS) {
+            return None;
+        }
+        let transfer_seconds = self.transfer_duration.as_secs() as f64 +
+                               self.transfer_duration.subsec_nanos() as f64 / 1e9;
+        Some(self.transferred_bytes as f64 / transfer_seconds)
+    }
+}
+
+/// Structure that distributes the requests of the workers between the mirrors of a remote
+/// content.
+/// Each request goes to the healthy mirror which is the least used. The mirrors which fail too
+/// often, or which are much slower than the others, are demoted - they are not used anymore,
+/// except if every other mirror is demoted too.
+#[derive(Clone)]
+pub struct MirrorPool {
+    mirrors: Arc<Vec<Mirror>>,
+    health: Arc<Mutex<Vec<MirrorHealth>>>,
+}
+
+impl MirrorPool {
+    /// Create a new pool of mirrors - the first one is the main URL of the remote content
+    pub fn new(mirrors: Vec<Mirror>) -> MirrorPool {
+        let health = mirrors.iter().map(|_| MirrorHealth::default()).collect();
+        MirrorPool {
+            mirrors: Arc::new(mirrors),
+            health: Arc::new(Mutex::new(health)),
+        }
+    }
+
+    /// Create a new pool which contains a single URL
+    pub fn single(url: &str, auth_header: Option<AuthorizationHeaderFactory>) -> MirrorPool {
+        MirrorPool::new(vec![Mirror {
+                                 url: String::from(url),
+                                 auth_header,
+                                 etag: None,
+                                 last_modified: None,
+                             }])
+    }
+
+    pub fn mirrors(&self) -> &[Mirror] {
+        &self.mirrors
+    }
+
+    /// Get the index of the mirror to send the next request to.
+    /// The mirror must be given back, using `release`, once the request is done.
+    pub fn acquire(&self) -> usize {
+        let mut health = self.health.lock().unwrap();
+        let mirror_index = (0..health.len())
+            .filter(|mirror_index| !health[*mirror_index].demoted)
+            .min_by_key(|mirror_index| health[*mirror_index].active)
+            .unwrap_or(0);
+        health[mirror_index].active += 1;
+        mirror_index
+    }
+
+    pub fn release(&self, mirror_index: usize) {
+        let mut health = self.health.lock().unwrap();
+        health[mirror_index].active -= 1;
+    }
+
+    /// Check if the given mirror should not be used anymore
+    pub fn is_demoted(&self, mirror_index: usize) -> bool {
+        self.health.lock().unwrap()[mirror_index].demoted
+    }
+
+    /// Report that a request to the given mirror failed, and return true if the mirror has been
+    /// demoted.
+    /// A mirror is demoted at once if the same request cannot succeed by sending it again.
+    /// A local error, such as a full disk, is not the fault of the mirror.
+    pub fn report_failure(&self, mirror_index: usize, error: &SnatchError) -> bool {
+        if error.is_local() {
+            return false;
+        }
+        let mut health = self.health.lock().unwrap();
+        health[mirror_index].failures += 1;
+        if !error.is_retryable() || health[mirror_index].failures >= MAX_MIRROR_FAILURES {
+            demote(&mut health, mirror_index)
+        } else {
+            false
+        }
+    }
+
+    /// Report that some bytes have been received from the given mirror, during the given
+    /// duration.
+    /// The mirror is demoted if it is much slower than the fastest mirror.
+    pub fn report_transfer(&self, mirror_index: usize, bytes: Bytes, duration: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health[mirror_index].transferred_bytes += bytes;
+        health[mirror_index].transfer_duration += duration;
+
+        let fastest_speed = health
+            .iter()
+            .filter(|mirror_health| !mirror_health.demoted)
+            .filter_map(|mirror_health| mirror_health.speed())
+            .fold(0.0, f64::max);
+        match health[mirror_index].speed() {
+            Some(speed) if speed * SLOW_MIRROR_RATIO < fastest_speed => {
+                demote(&mut health, mirror_index);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Function to demote a mirror, if it is not the last healthy one.
+/// This function returns true if the mirror has been demoted.
+fn demote(health: &mut [MirrorHealth], mirror_index: usize) -> bool {
+    let healthy_mirrors = health.iter().filter(|mirror_health| !mirror_health.demoted).count();
+    if health[mirror_index].demoted || healthy_mirrors <= 1 {
+        return false;
+    }
+    health[mirror_index].demoted = true;
+    true
+}
+
+/// Function to check that a mirror provides the same remote content as the main URL: the same
+/// length, and the same validator.
+/// The entity tags are compared if both of them send one, the modification dates otherwise: a
+/// mirror which does not send the validator of the main URL is rejected.
+pub fn check_mirror(reference: &CargoInfo, mirror: &CargoInfo) -> Result<(), String> {
+    match (mirror.content_length, reference.content_length) {
+        (Some(mirror_length), Some(reference_length)) if mirror_length != reference_length => {
+            return Err(format!("its length is {} bytes instead of {}",
+                               mirror_length,
+                               reference_length));
+        }
+        (None, Some(_)) => return Err(String::from("its length is unknown")),
+        (Some(_), None) => {
+            return Err(String::from("the length of the main URL is unknown, so the content of \
+                                     the mirror cannot be compared"))
+        }
+        _ => {}
+    }
+    if let (Some(reference_etag), Some(mirror_etag)) = (&reference.etag, &mirror.etag) {
+        if reference_etag != mirror_etag {
+            return Err(format!("its entity tag is {} instead of {}", mirror_etag, reference_etag));
+        }
+    } else {
+        match (&reference.last_modified, &mirror.last_modified) {
+            (Some(reference_date), Some(mirror_date)) if reference_date != mirror_date => {
+                return Err(format!("its modification date is {} instead of {}",
+                                   mirror_date,
+                                   reference_date));
+            }
+            (Some(_), None) => return Err(String::from("its modification date is unknown")),
+            (None, _) if reference.etag.is_some() => {
+                return Err(String::from("its entity tag is unknown"))
+            }
+            _ => {}
+        }
+    }
+    if reference.accept_partialcontent && !mirror.accept_partialcontent {
+        return Err(String::from("it does not accept PartialContent status"));
+    }
+    Ok(())
+}
+
+/// Function to get the informations of each mirror of a remote content, and to build the pool of
+/// the mirrors which provide the same remote content as the main URL.
+/// The mirrors which cannot be reached, or which are not consistent with the main URL, are
+/// ignored.
+/// Each mirror is then requested using its final URL, so the redirections are not followed again.
+pub fn probe_mirrors(cargo_info: &CargoInfo,
+                     mirror_urls: &[String],
+                     credential_provider: &dyn CredentialProvider,
+                     auth_header: Option<AuthorizationHeaderFactory>,
+                     timeouts: &Timeouts,
+                     redirect_policy: &RedirectPolicy)
+                     -> MirrorPool {
+    let mut mirrors = vec![Mirror {
+                               url: cargo_info.url.clone(),
+                               auth_header: cargo_info.auth_header.clone(),
+                               etag: cargo_info.etag.clone(),
+                               last_modified: cargo_info.last_modified.clone(),
+                           }];
+
+    for mirror_url in mirror_urls {
+        let mirror_info = get_cargo_info(mirror_url,
+                                         credential_provider,
+                                         auth_header.clone(),
+                                         timeouts,
+                                         redirect_policy)
+            .map_err(|error| error.to_string())
+            .and_then(|mirror_info| {
+                          check_mirror(cargo_info, &mirror_info).map(|_| mirror_info)
+                      });
+        match mirror_info {
+            Ok(mirror_info) => {
+                mirrors.push(Mirror {
+                                 url: mirror_info.url,
+                                 auth_header: mirror_info.auth_header,
+                                 etag: mirror_info.etag,
+                                 last_modified: mirror_info.last_modified,
+                             })
+            }
+            Err(reason) => {
+                warning!(&format!("Ignoring the mirror {}: {}", mirror_url, reason));
+            }
+        }
+    }
+
+    MirrorPool::new(mirrors)
+}
+
+#[cfg(test)]
+mod test_mirrors {
+
+    use error::SnatchError;
+    use hyper::header::Headers;
+    use hyper::status::StatusCode;
+    use std::time::Duration;
+    use cargo_helper::CargoInfo;
+    use super::{Mirror, MirrorPool, check_mirror};
+
+    fn new_pool(nb_mirrors: usize) -> MirrorPool {
+        MirrorPool::new((0..nb_mirrors)
+                            .map(|mirror_index| {
+                                     Mirror {
+                                         url: format!("http://mirror{}/file.zip", mirror_index),
+                                         auth_header: None,
+                                         etag: None,
+                                         last_modified: None,
+                                     }
+                                 })
+                            .collect())
+    }
+
+    fn new_cargo_info(etag: Option<&str>, last_modified: Option<&str>) -> CargoInfo {
+        CargoInfo {
+            url: String::from("http://mirror/file.zip"),
+            redirects: Vec::new(),
+            accept_partialcontent: true,
+            auth_header: None,
+            checksums: Vec::new(),
+            content_length: Some(1000),
+            etag: etag.map(String::from),
+            last_modified: last_modified.map(String::from),
+            file_name: None,
+        }
+    }
+
+    #[test]
+    fn requests_should_go_to_the_least_used_mirror() {
+        let pool = new_pool(2);
+        assert_eq!(0, pool.acquire());
+        assert_eq!(1, pool.acquire());
+        pool.release(0);
+        assert_eq!(0, pool.acquire());
+    }
+
+    #[test]
+    fn failing_mirror_should_be_demoted() {
+        let pool = new_pool(2);
+        assert!(pool.report_failure(0, &SnatchError::HttpStatus(StatusCode::NotFound)));
+        assert!(pool.is_demoted(0));
+        assert_eq!(1, pool.acquire());
+        assert_eq!(1, pool.acquire());
+    }
+
+    #[test]
+    fn last_healthy_mirror_should_not_be_demoted() {
+        let pool = new_pool(1);
+        assert!(!pool.report_failure(0, &SnatchError::HttpStatus(StatusCode::NotFound)));
+        assert!(!pool.is_demoted(0));
+    }
+
+    #[test]
+    fn slow_mirror_should_be_demoted() {
+        let pool = new_pool(3);
+        pool.report_transfer(0, 1000000, Duration::from_secs(1));
+        pool.report_transfer(1, 800000, Duration::from_secs(1));
+        assert!(!pool.is_demoted(1));
+        // The speed of a mirror is not known yet
+        pool.report_transfer(2, 10000, Duration::from_millis(500));
+        assert!(!pool.is_demoted(2));
+        pool.report_transfer(2, 10000, Duration::from_millis(500));
+        assert!(pool.is_demoted(2));
+    }
+
+    #[test]
+    fn if_range_should_use_a_strong_entity_tag() {
+        let mut mirror = Mirror {
+            url: String::from("http://mirror/file.zip"),
+            auth_header: None,
+            etag: Some(String::from("\"abc\"")),
+            last_modified: Some(String::from("Wed, 21 Oct 2015 07:28:00 GMT")),
+        };
+        assert_eq!(Some("\"abc\""), mirror.if_range());
+        mirror.etag = Some(String::from("W/\"abc\""));
+        assert_eq!(Some("Wed, 21 Oct 2015 07:28:00 GMT"), mirror.if_range());
+    }
+
+    #[test]
+    fn other_entity_tag_should_be_another_content() {
+        let mirror = Mirror {
+            url: String::from("http://mirror/file.zip"),
+            auth_header: None,
+            etag: Some(String::from("\"abc\"")),
+            last_modified: None,
+        };
+        let mut headers = Headers::new();
+        headers.set_raw("ETag", vec![b"\"abc\"".to_vec()]);
+        assert!(mirror.has_same_content(&headers));
+        headers.set_raw("ETag", vec![b"\"def\"".to_vec()]);
+        assert!(!mirror.has_same_content(&headers));
+    }
+
+    #[test]
+    fn mirror_without_the_entity_tag_should_be_checked_by_its_modification_date() {
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let other_date = "Thu, 22 Oct 2015 07:28:00 GMT";
+        let reference = new_cargo_info(Some("\"abc\""), Some(date));
+        assert!(check_mirror(&reference, &new_cargo_info(Some("\"abc\""), None)).is_ok());
+        assert!(check_mirror(&reference, &new_cargo_info(Some("\"def\""), Some(date))).is_err());
+        assert!(check_mirror(&reference, &new_cargo_info(None, Some(date))).is_ok());
+        assert!(check_mirror(&reference, &new_cargo_info(None, Some(other_date))).is_err());
+        assert!(check_mirror(&reference, &new_cargo_info(None, None)).is_err());
+        // Without a modification date, the entity tag of the main URL is required
+        let reference = new_cargo_info(Some("\"abc\""), None);
+        assert!(check_mirror(&reference, &new_cargo_info(None, Some(date))).is_err());
+    }
+
+    #[test]
+    fn mirror_should_be_checked_by_its_modification_date_without_entity_tags() {
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let other_date = "Thu, 22 Oct 2015 07:28:00 GMT";
+        let reference = new_cargo_info(None, Some(date));
+        assert!(check_mirror(&reference, &new_cargo_info(None, Some(date))).is_ok());
+        assert!(check_mirror(&reference, &new_cargo_info(None, Some(other_date))).is_err());
+        assert!(check_mirror(&reference, &new_cargo_info(None, None)).is_err());
+        // Without any validator, only the length is compared
+        let reference = new_cargo_info(None, None);
+        assert!(check_mirror(&reference, &new_cargo_info(Some("\"abc\""), None)).is_ok());
+    }
+
+}
@@ -0,0 +1,185 @@
+use Bytes;
+use checksum::{Checksum, Hasher};
+use error::SnatchError;
+use state::{ChunkState, StateTracker};
+use std::cmp::min;
+use std::sync::{Arc, Mutex};
+use write::OutputFileWriter;
+
+/// Constant to represent the length of the buffer used to read back a piece, in order to hash it
+const PIECE_BUFFER_BYTES: usize = 1024 * 64;
+
+/// Structure that contains the checksums of the pieces of a remote content.
+/// Each piece has the same length, except the last one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pieces {
+    pub length: Bytes,
+    pub checksums: Vec<Checksum>,
+}
+
+impl Pieces {
+    /// Check if these pieces cover exactly a remote content of the given length
+    pub fn matches(&self, content_length: Bytes) -> bool {
+        self.length > 0 &&
+        self.checksums.len() as Bytes == content_length.div_ceil(self.length)
+    }
+}
+
+/// Structure that contains the verification progress of a piece
+#[derive(Clone, Default)]
+struct PieceState {
+    verified: bool,
+    /// The piece is checked once it is written by the chunks from this index - the chunks
+    /// before have written corrupt bytes
+    first_chunk_index: usize,
+    attempts: u32,
+}
+
+/// Structure that checks the pieces of a download as soon as their bytes are written.
+/// A corrupt piece is downloaded again, as a new chunk of the download state.
+#[derive(Clone)]
+pub struct PieceVerifier {
+    pieces: Arc<Pieces>,
+    content_length: Bytes,
+    max_attempts: u32,
+    states: Arc<Mutex<Vec<PieceState>>>,
+}
+
+impl PieceVerifier {
+    /// Create a new verifier, which downloads again a corrupt piece at most `max_attempts` times
+    pub fn new(pieces: Pieces, content_length: Bytes, max_attempts: u32) -> PieceVerifier {
+        let states = vec![PieceState::default(); pieces.checksums.len()];
+        PieceVerifier {
+            pieces: Arc::new(pieces),
+            content_length,
+            max_attempts,
+            states: Arc::new(Mutex::new(states)),
+        }
+    }
+
+    /// Function to get the first and the last byte of a piece
+    fn piece_range(&self, piece_index: usize) -> (Bytes, Bytes) {
+        let start = piece_index as Bytes * self.pieces.length;
+        (start, min(start + self.pieces.length, self.content_length) - 1)
+    }
+
+    /// Function to check every piece which has been completely written, and which is not
+    /// checked yet.
+    /// Each corrupt piece is added as a new chunk to the download state, so it is downloaded
+    /// again. This function returns an error if a piece is still corrupt after all its attempts.
+    pub fn verify(&self,
+                  out_file: &OutputFileWriter,
+                  state_tracker: &StateTracker)
+                  -> Result<(), SnatchError> {
+        let mut states = self.states.lock().unwrap();
+        let chunks = state_tracker.chunks();
+
+        for piece_index in 0..states.len() {
+            let (start, end) = self.piece_range(piece_index);
+            if states[piece_index].verified ||
+               !is_written(&chunks[states[piece_index].first_chunk_index..], start, end) {
+                continue;
+            }
+
+            let expected = &self.pieces.checksums[piece_index];
+            let computed = hash_range(out_file, expected, start, end)?;
+            if computed == *expected {
+                states[piece_index].verified = true;
+                continue;
+            }
+
+            states[piece_index].attempts += 1;
+            if states[piece_index].attempts >= self.max_attempts {
+                return Err(SnatchError::ChecksumMismatch {
+                               expected: expected.clone(),
+                               computed,
+                           });
+            }
+            states[piece_index].first_chunk_index = state_tracker.add_chunk(start, end);
+        }
+        Ok(())
+    }
+}
+
+/// Function to compute the checksum of some bytes of the local file, with the algorithm of the
+/// expected checksum
+fn hash_range(out_file: &OutputFileWriter,
+              expected: &Checksum,
+              start: Bytes,
+              end: Bytes)
+              -> Result<Checksum, SnatchError> {
+    let mut hasher = Hasher::new(expected.algorithm);
+    let mut buffer = vec![0u8; PIECE_BUFFER_BYTES];
+    let mut offset = start;
+    while offset <= end {
+        let bcount = min(PIECE_BUFFER_BYTES as Bytes, end - offset + 1) as usize;
+        out_file.read(offset, &mut buffer[..bcount])?;
+        hasher.update(&buffer[..bcount]);
+        offset += bcount as Bytes;
+    }
+    Ok(Checksum {
+           algorithm: expected.algorithm,
+           digest: hasher.finalize(),
+       })
+}
+
+/// Function to check if every byte from `start` to `end` (inclusive) has been written by the
+/// given chunks
+fn is_written(chunks: &[ChunkState], start: Bytes, end: Bytes) -> bool {
+    let mut written_ranges: Vec<(Bytes, Bytes)> = chunks
+        .iter()
+        .filter(|chunk| chunk.written > 0)
+        .map(|chunk| (chunk.start, chunk.start + chunk.written))
+        .collect();
+    written_ranges.sort();
+
+    // First byte which is not known to be written yet
+    let mut next_byte = start;
+    for (range_start, range_end) in written_ranges {
+        if range_start > next_byte {
+            break;
+        }
+        if range_end > next_byte {
+            next_byte = range_end;
+        }
+    }
+    next_byte > end
+}
+
+#[cfg(test)]
+mod test_pieces {
+
+    use state::ChunkState;
+    use super::{Pieces, is_written};
+
+    fn written_chunk(start: u64, end: u64, written: u64) -> ChunkState {
+        let mut chunk = ChunkState::new(start, end);
+        chunk.written = written;
+        chunk
+    }
+
+    #[test]
+    fn pieces_should_cover_the_whole_content() {
+        let pieces = Pieces {
+            length: 100,
+            checksums: vec![],
+        };
+        assert!(pieces.matches(0));
+        assert!(!pieces.matches(1));
+    }
+
+    #[test]
+    fn range_written_by_several_chunks_should_be_written() {
+        let chunks = vec![written_chunk(0, 99, 100), written_chunk(100, 199, 50)];
+        assert!(is_written(&chunks, 50, 149));
+        assert!(!is_written(&chunks, 50, 150));
+    }
+
+    #[test]
+    fn range_with_a_hole_should_not_be_written() {
+        let chunks = vec![written_chunk(0, 99, 40), written_chunk(100, 199, 100)];
+        assert!(!is_written(&chunks, 0, 199));
+        assert!(is_written(&chunks, 100, 199));
+    }
+
+}
@@ -0,0 +1,132 @@
+use Bytes;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Constant to represent how long (in milliseconds) a connection may receive bytes at full
+/// speed, after being idle
+const BURST_MILLIS: u64 = 250;
+
+/// Structure that contains the tokens of a token bucket: one token for each byte that can be
+/// received
+struct TokenBucket {
+    /// The rate of the limit, in bytes per second
+    rate: f64,
+    capacity: f64,
+    /// The available tokens - negative if the received bytes have not been paid back yet
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Structure that limits the throughput of one or several connections, using a shared token
+/// bucket
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Create a new limiter, for the given rate in bytes per second
+    pub fn new(rate: Bytes) -> RateLimiter {
+        let rate = rate.max(1) as f64;
+        let capacity = rate * BURST_MILLIS as f64 / 1000.0;
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(TokenBucket {
+                                            rate,
+                                            capacity,
+                                            tokens: capacity,
+                                            last_refill: Instant::now(),
+                                        })),
+        }
+    }
+
+    /// Function to take `bytes` tokens from the bucket, and to get how long the caller has to
+    /// wait before receiving more bytes
+    fn take(&self, bytes: Bytes) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill);
+        let elapsed_seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        bucket.tokens = (bucket.tokens + elapsed_seconds * bucket.rate).min(bucket.capacity);
+        bucket.last_refill = now;
+
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens >= 0.0 {
+            Duration::from_millis(0)
+        } else {
+            Duration::from_millis((-bucket.tokens / bucket.rate * 1000.0).ceil() as u64)
+        }
+    }
+
+    /// Function to report that `bytes` bytes have been received, and to wait until the rate
+    /// goes back under the limit
+    pub fn consume(&self, bytes: Bytes) {
+        let delay = self.take(bytes);
+        if delay > Duration::from_millis(0) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Structure that contains the bandwidth limits of the downloads: a limit shared by every
+/// connection, and a limit for each connection
+#[derive(Clone, Default)]
+pub struct Throttle {
+    total: Option<RateLimiter>,
+    connection_rate: Option<Bytes>,
+    connection: Option<RateLimiter>,
+}
+
+impl Throttle {
+    /// Create a new throttle, given the maximum rates (in bytes per second) of all the
+    /// connections together and of each connection
+    pub fn new(total_rate: Option<Bytes>, connection_rate: Option<Bytes>) -> Throttle {
+        Throttle {
+            total: total_rate.map(RateLimiter::new),
+            connection_rate,
+            connection: None,
+        }
+    }
+
+    /// Get the throttle of a new connection, which shares the total limit with the other
+    /// connections, and has its own connection limit
+    pub fn for_connection(&self) -> Throttle {
+        Throttle {
+            total: self.total.clone(),
+            connection_rate: self.connection_rate,
+            connection: self.connection_rate.map(RateLimiter::new),
+        }
+    }
+
+    /// Function to report that a connection has received `bytes` bytes, and to wait until its
+    /// rate goes back under the limits
+    pub fn consume(&self, bytes: Bytes) {
+        if let Some(ref connection) = self.connection {
+            connection.consume(bytes);
+        }
+        if let Some(ref total) = self.total {
+            total.consume(bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_rate_limiter {
+
+    use std::time::Duration;
+    use super::RateLimiter;
+
+    #[test]
+    fn burst_should_not_wait() {
+        let limiter = RateLimiter::new(1000);
+        assert_eq!(Duration::from_millis(0), limiter.take(250));
+    }
+
+    #[test]
+    fn bytes_over_the_limit_should_wait() {
+        let limiter = RateLimiter::new(1000);
+        let delay = limiter.take(1250);
+        assert!(delay >= Duration::from_millis(990) && delay <= Duration::from_millis(1000));
+    }
+
+}
@@ -0,0 +1,190 @@
+use authorization::AuthorizationHeaderFactory;
+use error::SnatchError;
+use hyper::Url;
+use hyper::client::response::Response;
+use hyper::header::{Headers, Location};
+use hyper::status::StatusCode;
+
+/// Constant to represent the default maximum number of redirections followed to get a remote
+/// content
+pub const DEFAULT_MAX_REDIRECTS: u32 = 10;
+
+/// Structure that describes how the redirections sent by the remote servers are followed
+#[derive(Clone, Debug, PartialEq)]
+pub struct RedirectPolicy {
+    /// The maximum number of redirections followed to get a remote content
+    pub max_redirects: u32,
+    /// Follow the redirections from HTTPS to HTTP
+    pub allow_downgrade: bool,
+    /// Send the authorization to the other hosts the remote content is redirected to
+    pub forward_credentials: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> RedirectPolicy {
+        RedirectPolicy {
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            allow_downgrade: false,
+            forward_credentials: false,
+        }
+    }
+}
+
+/// Structure that contains the response at the end of the redirections, with the URL which sent
+/// it
+pub struct Redirected {
+    pub response: Response,
+    /// The final URL of the remote content
+    pub url: String,
+    /// The authorization which has been sent to the final URL
+    pub auth_header: Option<AuthorizationHeaderFactory>,
+    /// The URLs which have been redirected, in order
+    pub redirects: Vec<String>,
+}
+
+impl RedirectPolicy {
+    /// Function to send a request using `send`, following the redirections of the remote
+    /// servers as described by this policy.
+    /// The authorization is sent to the hosts the remote content is redirected to only if
+    /// the policy allows it.
+    pub fn follow<F>(&self,
+                     url: &str,
+                     auth_header: Option<AuthorizationHeaderFactory>,
+                     mut send: F)
+                     -> Result<Redirected, SnatchError>
+        where F: FnMut(&str, &Option<AuthorizationHeaderFactory>) -> Result<Response, SnatchError>
+    {
+        let mut url = String::from(url);
+        let mut auth_header = auth_header;
+        let mut redirects = vec![];
+
+        loop {
+            let response = send(&url, &auth_header)?;
+            let next_url = match self.redirect_url(&url, response.status, &response.headers)? {
+                Some(next_url) => next_url,
+                None => {
+                    return Ok(Redirected {
+                                  response,
+                                  url,
+                                  auth_header,
+                                  redirects,
+                              })
+                }
+            };
+            if redirects.len() as u32 >= self.max_redirects {
+                return Err(SnatchError::TooManyRedirects(self.max_redirects));
+            }
+            if !(self.forward_credentials || is_same_origin(&url, &next_url)) {
+                auth_header = None;
+            }
+            redirects.push(url);
+            url = next_url;
+        }
+    }
+
+    /// Function to get the URL a response redirects to, or None if it is not a redirection.
+    /// This function returns an error if the redirection is not allowed by the policy.
+    fn redirect_url(&self,
+                    url: &str,
+                    status: StatusCode,
+                    headers: &Headers)
+                    -> Result<Option<String>, SnatchError> {
+        match status {
+            StatusCode::MovedPermanently |
+            StatusCode::Found |
+            StatusCode::SeeOther |
+            StatusCode::TemporaryRedirect |
+            StatusCode::PermanentRedirect => {}
+            _ => return Ok(None),
+        }
+        let location = match headers.get::<Location>() {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+
+        // The location may be relative to the redirected URL
+        let next_url = Url::parse(url)
+            .and_then(|url| url.join(location))
+            .map_err(|_| SnatchError::InvalidRedirect(location.to_string()))?;
+        match next_url.scheme() {
+            "http" if !self.allow_downgrade && url.starts_with("https:") => {
+                Err(SnatchError::InvalidRedirect(format!("{} is not secure", next_url)))
+            }
+            "http" | "https" => Ok(Some(next_url.into_string())),
+            _ => Err(SnatchError::InvalidRedirect(next_url.into_string())),
+        }
+    }
+}
+
+/// Function to check that two URLs have the same host and port
+fn is_same_origin(url: &str, other_url: &str) -> bool {
+    match (Url::parse(url), Url::parse(other_url)) {
+        (Ok(url), Ok(other_url)) => {
+            url.host_str() == other_url.host_str() &&
+            url.port_or_known_default() == other_url.port_or_known_default()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test_redirect {
+
+    use hyper::header::{Headers, Location};
+    use hyper::status::StatusCode;
+    use super::{RedirectPolicy, is_same_origin};
+
+    fn location(location: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.set(Location(String::from(location)));
+        headers
+    }
+
+    #[test]
+    fn relative_location_should_be_resolved() {
+        let policy = RedirectPolicy::default();
+        assert_eq!(Some(String::from("https://example.com/files/other.zip")),
+                   policy
+                       .redirect_url("https://example.com/files/file.zip",
+                                     StatusCode::Found,
+                                     &location("other.zip"))
+                       .unwrap());
+        assert_eq!(None,
+                   policy
+                       .redirect_url("https://example.com/file.zip",
+                                     StatusCode::Ok,
+                                     &location("/other.zip"))
+                       .unwrap());
+    }
+
+    #[test]
+    fn downgrade_should_be_refused_unless_allowed() {
+        let mut policy = RedirectPolicy::default();
+        let headers = location("http://example.com/file.zip");
+        assert!(policy
+                    .redirect_url("https://example.com/file.zip",
+                                  StatusCode::MovedPermanently,
+                                  &headers)
+                    .is_err());
+        policy.allow_downgrade = true;
+        assert!(policy
+                    .redirect_url("https://example.com/file.zip",
+                                  StatusCode::MovedPermanently,
+                                  &headers)
+                    .is_ok());
+        assert!(policy
+                    .redirect_url("https://example.com/file.zip",
+                                  StatusCode::Found,
+                                  &location("ftp://example.com/file.zip"))
+                    .is_err());
+    }
+
+    #[test]
+    fn other_host_should_be_another_origin() {
+        assert!(is_same_origin("http://example.com/file.zip", "http://EXAMPLE.com:80/other"));
+        assert!(!is_same_origin("http://example.com/file.zip",
+                                "http://bucket.s3.amazonaws.com/file.zip"));
+        assert!(!is_same_origin("http://example.com/file.zip", "https://example.com/file.zip"));
+    }
+
+}
@@ -0,0 +1,123 @@
+use rand::random;
+use std::cmp::min;
+use std::time::Duration;
+
+/// Default maximum number of attempts to download a chunk
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Default delay (in milliseconds) before the first retry of a chunk
+pub const DEFAULT_BASE_DELAY_MILLIS: u64 = 500;
+
+/// Default maximum delay (in milliseconds) between two attempts
+pub const DEFAULT_MAX_DELAY_MILLIS: u64 = 30_000;
+
+/// Structure that describes how a failed chunk is downloaded again.
+/// The delay before each retry grows exponentially from `base_delay`, up to `max_delay`.
+/// If `jitter` is set, a random part of this delay is removed, to avoid retrying every chunk
+/// at the same time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MILLIS),
+            max_delay: Duration::from_millis(DEFAULT_MAX_DELAY_MILLIS),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Get a policy which never retries a failed chunk
+    pub fn no_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Check if another attempt can be made, after `attempts` failed attempts
+    pub fn should_retry(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    /// Get the delay to wait before the retry number `retry` (starting from 1)
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = min(self.backoff(retry), self.max_delay);
+        if !self.jitter {
+            return delay;
+        }
+        // Keep at least the half of the delay, and pick randomly the other half
+        let delay_millis = duration_as_millis(delay);
+        let jitter_millis = (random::<f64>() * (delay_millis / 2) as f64) as u64;
+        Duration::from_millis(delay_millis - jitter_millis)
+    }
+
+    /// Get the exponential delay for the retry number `retry`, without any limit
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+    }
+}
+
+fn duration_as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000 + duration.subsec_millis() as u64
+}
+
+#[cfg(test)]
+mod test_retry_policy {
+
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    fn policy_without_jitter() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1_000),
+            jitter: false,
+        }
+    }
+
+    #[test]
+    fn delay_should_grow_exponentially() {
+        let policy = policy_without_jitter();
+        assert_eq!(Duration::from_millis(100), policy.delay(1));
+        assert_eq!(Duration::from_millis(200), policy.delay(2));
+        assert_eq!(Duration::from_millis(400), policy.delay(3));
+    }
+
+    #[test]
+    fn delay_should_not_exceed_the_max_delay() {
+        let policy = policy_without_jitter();
+        assert_eq!(Duration::from_millis(1_000), policy.delay(5));
+        assert_eq!(Duration::from_millis(1_000), policy.delay(64));
+    }
+
+    #[test]
+    fn jitter_should_keep_at_least_the_half_of_the_delay() {
+        let policy = RetryPolicy { jitter: true, ..policy_without_jitter() };
+        for _ in 0..100 {
+            let delay = policy.delay(3);
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    fn should_retry_until_the_max_attempts() {
+        let policy = policy_without_jitter();
+        assert!(policy.should_retry(3));
+        assert!(!policy.should_retry(4));
+        assert!(!RetryPolicy::no_retry().should_retry(1));
+    }
+
+}
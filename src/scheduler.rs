@@ -0,0 +1,129 @@
+use Bytes;
+use state::{ChunkState, StateTracker};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Number of chunks to split the remote content into, for each worker
+pub const CHUNKS_PER_WORKER: u64 = 4;
+
+/// Constant to represent the minimal length of the two halves of a split chunk.
+/// This length must be greater than the length of the download buffer plus the length of the
+/// write buffer, since the state only counts the written bytes: so a worker never writes beyond
+/// the new end of its chunk.
+const MIN_SPLIT_LENGTH_BYTES: Bytes = 1024 * 1024;
+
+/// Structure that distributes the chunks of a download between a fixed pool of workers.
+/// When there is no chunk left to give to an idle worker, the largest chunk which is being
+/// downloaded is split in half, and its second half is given to the idle worker - so the end of
+/// the download is never stuck on a single slow connection.
+#[derive(Clone)]
+pub struct ChunkScheduler {
+    state_tracker: StateTracker,
+    active_chunks: Arc<Mutex<HashSet<usize>>>,
+    allow_split: bool,
+}
+
+impl ChunkScheduler {
+    /// Create a new scheduler for the chunks of the given download state.
+    /// If `allow_split` is false, the chunks are never split - this is needed if the remote server
+    /// does not accept PartialContent status.
+    pub fn new(state_tracker: StateTracker, allow_split: bool) -> ChunkScheduler {
+        ChunkScheduler {
+            state_tracker,
+            active_chunks: Arc::new(Mutex::new(HashSet::new())),
+            allow_split,
+        }
+    }
+
+    /// Get the next chunk to download, with its index, or None if every chunk is either
+    /// complete or too small to be shared.
+    /// The returned chunk belongs to the caller until it calls `release`.
+    pub fn next_chunk(&self) -> Option<(usize, ChunkState)> {
+        let mut active_chunks = self.active_chunks.lock().unwrap();
+        let chunks = self.state_tracker.chunks();
+
+        // Take the first chunk which is neither complete nor downloaded by another worker
+        let pending_chunk = (0..chunks.len()).find(|chunk_index| {
+            !chunks[*chunk_index].is_complete() && !active_chunks.contains(chunk_index)
+        });
+        if let Some(chunk_index) = pending_chunk {
+            active_chunks.insert(chunk_index);
+            return Some((chunk_index, chunks[chunk_index].clone()));
+        }
+
+        if !self.allow_split {
+            return None;
+        }
+
+        // Else, help the worker which has the most bytes left to download
+        let largest_chunk = active_chunks
+            .iter()
+            .cloned()
+            .max_by_key(|chunk_index| chunks[*chunk_index].remaining());
+        largest_chunk
+            .and_then(|chunk_index| {
+                          self.state_tracker
+                              .split_chunk(chunk_index, MIN_SPLIT_LENGTH_BYTES)
+                      })
+            .map(|new_chunk_index| {
+                     active_chunks.insert(new_chunk_index);
+                     (new_chunk_index, self.state_tracker.chunk(new_chunk_index))
+                 })
+    }
+
+    /// Give back a chunk to the scheduler, once its worker does not download it anymore.
+    /// If the chunk is not complete, it is given to the next idle worker.
+    pub fn release(&self, chunk_index: usize) {
+        self.active_chunks.lock().unwrap().remove(&chunk_index);
+    }
+}
+
+#[cfg(test)]
+mod test_scheduler {
+
+    use state::StateTracker;
+    use state::test_state::new_state;
+    use std::env::temp_dir;
+    use super::ChunkScheduler;
+
+    fn new_scheduler(content_length: u64, nb_chunks: u64, allow_split: bool) -> ChunkScheduler {
+        let state = new_state(content_length, nb_chunks);
+        ChunkScheduler::new(StateTracker::new(state, temp_dir().join("snatch_scheduler.snatch")),
+                            allow_split)
+    }
+
+    #[test]
+    fn pending_chunks_should_be_given_in_order() {
+        let scheduler = new_scheduler(1000, 2, true);
+        assert_eq!(Some(0), scheduler.next_chunk().map(|(index, _)| index));
+        assert_eq!(Some(1), scheduler.next_chunk().map(|(index, _)| index));
+    }
+
+    #[test]
+    fn released_chunk_should_be_given_again() {
+        let scheduler = new_scheduler(1000, 1, false);
+        assert_eq!(Some(0), scheduler.next_chunk().map(|(index, _)| index));
+        assert_eq!(None, scheduler.next_chunk());
+        scheduler.release(0);
+        assert_eq!(Some(0), scheduler.next_chunk().map(|(index, _)| index));
+    }
+
+    #[test]
+    fn largest_active_chunk_should_be_split_for_an_idle_worker() {
+        let scheduler = new_scheduler(8 * 1024 * 1024, 2, true);
+        scheduler.next_chunk();
+        scheduler.next_chunk();
+
+        let (new_index, new_chunk) = scheduler.next_chunk().unwrap();
+        assert_eq!(2, new_index);
+        assert_eq!(2 * 1024 * 1024 + 1, new_chunk.length());
+    }
+
+    #[test]
+    fn small_chunks_should_not_be_split() {
+        let scheduler = new_scheduler(1000, 1, true);
+        scheduler.next_chunk();
+        assert_eq!(None, scheduler.next_chunk());
+    }
+
+}
@@ -0,0 +1,990 @@
+use Bytes;
+use Chunk;
+use checksum::{Checksum, ChecksumVerifier};
+use error::SnatchError;
+use memmap2::MmapRaw;
+use state::ChunkState;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, read_dir, remove_file};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Constant to represent the default maximum number of bytes received ahead of the output which
+/// are kept in memory
+pub const DEFAULT_REORDER_BUFFER_BYTES: usize = 64 * 1024 * 1024;
+
+/// Constant to represent the length of the buffer of zeros written to allocate a file, where the
+/// platform cannot reserve its blocks
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+const ALLOCATE_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// Number of spill files created by this process, to give each one its own name
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Enum for the storages of the local file the user can choose
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Storage {
+    /// A regular file, see `FileSink`
+    File,
+    /// A memory-mapped file, see `MmapSink`
+    Mmap,
+    /// A part file for each chunk, see `PartFilesSink`
+    PartFiles,
+}
+
+impl Storage {
+    /// Function to get a storage from its name (`file`, `mmap` or `parts`)
+    pub fn from_name(name: &str) -> Option<Storage> {
+        match name.to_lowercase().as_str() {
+            "file" => Some(Storage::File),
+            "mmap" => Some(Storage::Mmap),
+            "parts" => Some(Storage::PartFiles),
+            _ => None,
+        }
+    }
+}
+
+/// Enum for the ways to allocate the local file before downloading the remote content into it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prealloc {
+    /// The local file grows as the chunks are written
+    None,
+    /// The local file gets the length of the remote content at once, without allocating its
+    /// blocks
+    Sparse,
+    /// The blocks of the local file are reserved at once, so the disk cannot get full during the
+    /// download, and the local file is less fragmented
+    Full,
+}
+
+impl Prealloc {
+    /// Function to get a preallocation from its name (`none`, `sparse` or `full`)
+    pub fn from_name(name: &str) -> Option<Prealloc> {
+        match name.to_lowercase().as_str() {
+            "none" => Some(Prealloc::None),
+            "sparse" => Some(Prealloc::Sparse),
+            "full" => Some(Prealloc::Full),
+            _ => None,
+        }
+    }
+}
+
+/// Trait for the storages the chunks of a remote content are written into.
+/// The offsets are the offsets in the remote content. The chunks are written concurrently by the
+/// workers, but never at the same offsets.
+pub trait ChunkSink: Send + Sync {
+    /// Write some bytes at the given offset
+    fn write_at(&self, offset: Bytes, buf: &[u8]) -> Result<(), SnatchError>;
+
+    /// Read back some bytes which have been written, from the given offset
+    fn read_at(&self, offset: Bytes, buf: &mut [u8]) -> Result<(), SnatchError>;
+
+    /// Set the length of the remote content, truncating the bytes written beyond it
+    fn set_len(&self, length: Bytes) -> Result<(), SnatchError>;
+
+    /// Reserve the blocks of the storage for `length` bytes, where it is possible.
+    /// This function must be called before anything is written.
+    fn preallocate(&self, _length: Bytes) -> Result<(), SnatchError> {
+        Ok(())
+    }
+
+    /// Function to call before the chunk of the download state at `index` is written, with its
+    /// bounds: a storage may give its own place to each chunk
+    fn add_chunk(&self, _index: usize, _chunk: &ChunkState) -> Result<(), SnatchError> {
+        Ok(())
+    }
+
+    /// Function to call once every byte of the remote content is written: the storage writes
+    /// them to the disk, in their final place
+    fn finish(&self) -> Result<(), SnatchError>;
+}
+
+/// Structure that writes the chunks in a regular file, using positional writes
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    pub fn new(file: File) -> FileSink {
+        FileSink { file }
+    }
+}
+
+impl ChunkSink for FileSink {
+    fn write_at(&self, offset: Bytes, buf: &[u8]) -> Result<(), SnatchError> {
+        write_all_at(&self.file, buf, offset)?;
+        Ok(())
+    }
+
+    fn read_at(&self, offset: Bytes, buf: &mut [u8]) -> Result<(), SnatchError> {
+        read_exact_at(&self.file, buf, offset)?;
+        Ok(())
+    }
+
+    fn set_len(&self, length: Bytes) -> Result<(), SnatchError> {
+        self.file.set_len(length)?;
+        Ok(())
+    }
+
+    fn preallocate(&self, length: Bytes) -> Result<(), SnatchError> {
+        allocate_file(&self.file, length)?;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), SnatchError> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Structure that writes the chunks in a memory-mapped file.
+/// The file is mapped once its length is set, and nothing can be written beyond it.
+/// The blocks of the file are always reserved before it is mapped: writing in a hole of a sparse
+/// file raises SIGBUS when the disk is full, instead of an error.
+pub struct MmapSink {
+    file: File,
+    map: RwLock<Option<MmapRaw>>,
+}
+
+impl MmapSink {
+    /// Create a new sink for the given file, mapping its current content
+    pub fn new(file: File) -> Result<MmapSink, SnatchError> {
+        allocate_file(&file, file.metadata()?.len())?;
+        let map = map_file(&file)?;
+        Ok(MmapSink {
+               file,
+               map: RwLock::new(map),
+           })
+    }
+
+    /// Function to get the address of the mapped bytes from `offset` to `offset + length`, or an
+    /// error if they are out of the mapped file
+    fn mapped_range(map: &Option<MmapRaw>,
+                    offset: Bytes,
+                    length: usize)
+                    -> Result<*mut u8, SnatchError> {
+        match *map {
+            Some(ref map) if offset.saturating_add(length as Bytes) <= map.len() as Bytes => {
+                Ok(unsafe { map.as_mut_ptr().add(offset as usize) })
+            }
+            _ => {
+                Err(SnatchError::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                   "out of the mapped file")))
+            }
+        }
+    }
+}
+
+impl ChunkSink for MmapSink {
+    fn write_at(&self, offset: Bytes, buf: &[u8]) -> Result<(), SnatchError> {
+        let map = self.map.read().map_err(|_| poisoned())?;
+        let address = MmapSink::mapped_range(&map, offset, buf.len())?;
+        // The workers never write the same bytes, and the map is not changed meanwhile
+        unsafe { ptr::copy_nonoverlapping(buf.as_ptr(), address, buf.len()) };
+        Ok(())
+    }
+
+    fn read_at(&self, offset: Bytes, buf: &mut [u8]) -> Result<(), SnatchError> {
+        let map = self.map.read().map_err(|_| poisoned())?;
+        let address = MmapSink::mapped_range(&map, offset, buf.len())?;
+        unsafe { ptr::copy_nonoverlapping(address, buf.as_mut_ptr(), buf.len()) };
+        Ok(())
+    }
+
+    fn set_len(&self, length: Bytes) -> Result<(), SnatchError> {
+        let mut map = self.map.write().map_err(|_| poisoned())?;
+        if let Some(ref map) = *map {
+            map.flush()?;
+        }
+        *map = None;
+        self.file.set_len(length)?;
+        allocate_file(&self.file, length)?;
+        *map = map_file(&self.file)?;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), SnatchError> {
+        let map = self.map.read().map_err(|_| poisoned())?;
+        if let Some(ref map) = *map {
+            map.flush()?;
+        }
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Structure that keeps the chunks in memory, for the library users which want the bytes of the
+/// remote content
+pub struct MemorySink {
+    content: Mutex<Chunk>,
+}
+
+impl MemorySink {
+    pub fn new() -> MemorySink {
+        MemorySink { content: Mutex::new(vec![]) }
+    }
+
+    /// Function to get a copy of the bytes written so far
+    pub fn content(&self) -> Result<Chunk, SnatchError> {
+        Ok(self.content.lock().map_err(|_| poisoned())?.clone())
+    }
+}
+
+impl Default for MemorySink {
+    fn default() -> MemorySink {
+        MemorySink::new()
+    }
+}
+
+impl ChunkSink for MemorySink {
+    fn write_at(&self, offset: Bytes, buf: &[u8]) -> Result<(), SnatchError> {
+        let mut content = self.content.lock().map_err(|_| poisoned())?;
+        let (start, end) = (offset as usize, offset as usize + buf.len());
+        if content.len() < end {
+            content.resize(end, 0);
+        }
+        content[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn read_at(&self, offset: Bytes, buf: &mut [u8]) -> Result<(), SnatchError> {
+        let content = self.content.lock().map_err(|_| poisoned())?;
+        let (start, end) = (offset as usize, offset as usize + buf.len());
+        if content.len() < end {
+            return Err(SnatchError::Io(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                      "failed to fill whole buffer")));
+        }
+        buf.copy_from_slice(&content[start..end]);
+        Ok(())
+    }
+
+    fn set_len(&self, length: Bytes) -> Result<(), SnatchError> {
+        self.content
+            .lock()
+            .map_err(|_| poisoned())?
+            .resize(length as usize, 0);
+        Ok(())
+    }
+
+    fn preallocate(&self, length: Bytes) -> Result<(), SnatchError> {
+        let mut content = self.content.lock().map_err(|_| poisoned())?;
+        let additional = (length as usize).saturating_sub(content.len());
+        content.reserve(additional);
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), SnatchError> {
+        Ok(())
+    }
+}
+
+/// Structure that writes each chunk of the download state in its own part file, for the file
+/// systems which handle the random writes in a large sparse file poorly: each part file is
+/// written in order, from its beginning.
+/// A part file is named after the partial file and the index of its chunk. The chunks added
+/// while downloading - the second halves of the split chunks, and the corrupt pieces downloaded
+/// again - get their own part file too, which takes over the bytes of the previous part files in
+/// its range. The part files are concatenated into the partial file, in order, once the download
+/// is finished.
+pub struct PartFilesSink {
+    part_path: PathBuf,
+    /// The part files, by index of their chunk
+    parts: RwLock<BTreeMap<usize, PartFile>>,
+    length: Mutex<Option<Bytes>>,
+}
+
+/// Structure that contains a part file, and the bounds (inclusive) of the bytes it may contain
+struct PartFile {
+    start: Bytes,
+    end: Bytes,
+    file: File,
+}
+
+impl PartFilesSink {
+    /// Create the part files of a new download, for the given chunks - or a single part file if
+    /// the chunks are unknown.
+    /// The part files of a previous download are removed.
+    pub fn create(part_path: &Path, chunks: &[ChunkState]) -> Result<PartFilesSink, SnatchError> {
+        PartFilesSink::remove(part_path)?;
+        let sink = PartFilesSink {
+            part_path: part_path.to_path_buf(),
+            parts: RwLock::new(BTreeMap::new()),
+            length: Mutex::new(None),
+        };
+        for (index, chunk) in part_chunks(chunks).iter().enumerate() {
+            sink.add_chunk(index, chunk)?;
+        }
+        Ok(sink)
+    }
+
+    /// Open the part files of a previous download, to resume it, for the chunks of its state.
+    /// The part files of the chunks which are not in the state are removed, and the missing ones
+    /// are created.
+    pub fn open(part_path: &Path, chunks: &[ChunkState]) -> Result<PartFilesSink, SnatchError> {
+        let indexes = find_parts(part_path)?;
+        if indexes.first() != Some(&0) {
+            return Err(SnatchError::Io(io::Error::new(io::ErrorKind::NotFound,
+                                                      "the part files are missing")));
+        }
+        let chunks = part_chunks(chunks);
+        for &index in indexes.iter().filter(|&&index| index >= chunks.len()) {
+            remove_file(part_file_path(part_path, index))?;
+        }
+
+        let mut parts = BTreeMap::new();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(part_file_path(part_path, index))?;
+            parts.insert(index,
+                         PartFile {
+                             start: chunk.start,
+                             end: chunk.end,
+                             file,
+                         });
+        }
+        Ok(PartFilesSink {
+               part_path: part_path.to_path_buf(),
+               parts: RwLock::new(parts),
+               length: Mutex::new(None),
+           })
+    }
+
+    /// Function to check if some part files belong to the given partial file
+    pub fn exist(part_path: &Path) -> bool {
+        find_parts(part_path)
+            .map(|indexes| !indexes.is_empty())
+            .unwrap_or(false)
+    }
+
+    /// Function to remove the part files which belong to the given partial file, if any
+    pub fn remove(part_path: &Path) -> Result<(), SnatchError> {
+        for index in find_parts(part_path)? {
+            remove_file(part_file_path(part_path, index))?;
+        }
+        Ok(())
+    }
+
+    /// Function to call `apply` on each part of the range from `offset` to `offset + length`,
+    /// with its part file, its offset in this part file, and its bounds in the range
+    fn for_each_part<F>(&self, offset: Bytes, length: usize, mut apply: F) -> io::Result<()>
+        where F: FnMut(&File, Bytes, usize, usize) -> io::Result<()>
+    {
+        let parts = self.parts.read().map_err(|_| poisoned())?;
+        let mut done = 0;
+        while done < length {
+            let position = offset + done as Bytes;
+            // The last part file which contains a byte has its latest version
+            let (&index, part) = parts
+                .iter()
+                .rev()
+                .find(|&(_, part)| part.start <= position && position <= part.end)
+                .ok_or_else(|| {
+                                io::Error::new(io::ErrorKind::InvalidInput,
+                                               "out of the part files")
+                            })?;
+            // Until the end of this part file, or the beginning of a later one
+            let part_end = parts
+                .range(index + 1..)
+                .map(|(_, next_part)| next_part.start)
+                .filter(|&next_start| next_start > position)
+                .fold(part.end.saturating_add(1), Bytes::min);
+            let count = (part_end - position).min((length - done) as Bytes) as usize;
+            apply(&part.file, position - part.start, done, done + count)?;
+            done += count;
+        }
+        Ok(())
+    }
+}
+
+impl ChunkSink for PartFilesSink {
+    fn write_at(&self, offset: Bytes, buf: &[u8]) -> Result<(), SnatchError> {
+        self.for_each_part(offset,
+                           buf.len(),
+                           |file, part_offset, from, to| {
+                               write_all_at(file, &buf[from..to], part_offset)
+                           })?;
+        Ok(())
+    }
+
+    fn read_at(&self, offset: Bytes, buf: &mut [u8]) -> Result<(), SnatchError> {
+        let length = buf.len();
+        self.for_each_part(offset,
+                           length,
+                           |file, part_offset, from, to| {
+                               read_exact_at(file, &mut buf[from..to], part_offset)
+                           })?;
+        Ok(())
+    }
+
+    fn set_len(&self, length: Bytes) -> Result<(), SnatchError> {
+        *self.length.lock().map_err(|_| poisoned())? = Some(length);
+        Ok(())
+    }
+
+    fn preallocate(&self, length: Bytes) -> Result<(), SnatchError> {
+        let parts = self.parts.read().map_err(|_| poisoned())?;
+        for part in parts.values().filter(|part| part.start < length) {
+            allocate_file(&part.file, part.end.min(length - 1) - part.start + 1)?;
+        }
+        Ok(())
+    }
+
+    fn add_chunk(&self, index: usize, chunk: &ChunkState) -> Result<(), SnatchError> {
+        let mut parts = self.parts.write().map_err(|_| poisoned())?;
+        if parts.contains_key(&index) {
+            return Ok(());
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(part_file_path(&self.part_path, index))?;
+        // The second half of a split chunk is not copied from the part file of its first half
+        for part in parts.range_mut(..index).map(|(_, part)| part) {
+            if part.start < chunk.start && chunk.start <= part.end && part.end <= chunk.end {
+                part.end = chunk.start - 1;
+            }
+        }
+        parts.insert(index,
+                     PartFile {
+                         start: chunk.start,
+                         end: chunk.end,
+                         file,
+                     });
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), SnatchError> {
+        let mut out_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.part_path)?;
+        let parts = self.parts.read().map_err(|_| poisoned())?;
+        for part in parts.values() {
+            let mut file = &part.file;
+            file.seek(SeekFrom::Start(0))?;
+            out_file.seek(SeekFrom::Start(part.start))?;
+            io::copy(&mut file.take((part.end - part.start).saturating_add(1)),
+                     &mut out_file)?;
+        }
+        if let Some(length) = *self.length.lock().map_err(|_| poisoned())? {
+            out_file.set_len(length)?;
+        }
+        out_file.sync_all()?;
+        PartFilesSink::remove(&self.part_path)
+    }
+}
+
+/// Structure that writes the chunks to a stream - e.g. the standard output - strictly in order,
+/// while the chunks are received out of order.
+/// The bytes received ahead are held in memory until the bytes before them are written, and in a
+/// temporary spill file beyond the reorder buffer length. The bytes already written cannot be read
+/// back, nor written again.
+pub struct OrderedSink<W: Write + Send> {
+    output: Mutex<OrderedOutput<W>>,
+}
+
+struct OrderedOutput<W: Write + Send> {
+    writer: W,
+    /// The number of bytes already written to the stream
+    written: Bytes,
+    length: Option<Bytes>,
+    /// The bytes received ahead and kept in memory, by offset
+    held: BTreeMap<Bytes, Vec<u8>>,
+    held_bytes: usize,
+    reorder_buffer_bytes: usize,
+    /// The bytes received ahead and written in the spill file, by offset: their position in the
+    /// spill file and their length
+    spilled: BTreeMap<Bytes, (Bytes, usize)>,
+    spill_file: Option<(PathBuf, File)>,
+    spill_end: Bytes,
+    verifier: Option<ChecksumVerifier>,
+}
+
+impl<W: Write + Send> OrderedSink<W> {
+    /// Create a new sink which writes to `writer`, and keeps at most `reorder_buffer_bytes` bytes
+    /// in memory
+    pub fn new(writer: W, reorder_buffer_bytes: usize) -> OrderedSink<W> {
+        OrderedSink {
+            output: Mutex::new(OrderedOutput {
+                                   writer,
+                                   written: 0,
+                                   length: None,
+                                   held: BTreeMap::new(),
+                                   held_bytes: 0,
+                                   reorder_buffer_bytes,
+                                   spilled: BTreeMap::new(),
+                                   spill_file: None,
+                                   spill_end: 0,
+                                   verifier: None,
+                               }),
+        }
+    }
+
+    /// Compute the checksums of the written bytes, to check them with `verify` once finished
+    pub fn with_checksums(self, checksums: &[Checksum]) -> OrderedSink<W> {
+        if let Ok(mut output) = self.output.lock() {
+            output.verifier = Some(ChecksumVerifier::new(checksums));
+        }
+        self
+    }
+
+    /// Function to check the written bytes against the expected checksums, once finished
+    pub fn verify(&self) -> Result<(), SnatchError> {
+        let mut output = self.output.lock().map_err(|_| poisoned())?;
+        match output.verifier.take() {
+            Some(verifier) => verifier.verify(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write + Send> OrderedOutput<W> {
+    fn receive(&mut self, offset: Bytes, buf: &[u8]) -> io::Result<()> {
+        // The bytes already written are received again only when a request starts over
+        let skipped = (self.written.saturating_sub(offset)).min(buf.len() as Bytes) as usize;
+        let (offset, buf) = (offset + skipped as Bytes, &buf[skipped..]);
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        if offset == self.written {
+            self.emit(buf)?;
+            return self.drain();
+        }
+        if self.held_bytes + buf.len() <= self.reorder_buffer_bytes {
+            if let Some(previous) = self.held.insert(offset, buf.to_vec()) {
+                self.held_bytes -= previous.len();
+            }
+            self.held_bytes += buf.len();
+            return Ok(());
+        }
+        let position = self.spill_end;
+        write_all_at(self.spill_file()?, buf, position)?;
+        self.spill_end += buf.len() as Bytes;
+        self.spilled.insert(offset, (position, buf.len()));
+        Ok(())
+    }
+
+    /// Function to write the held bytes which follow the written ones, in order
+    fn drain(&mut self) -> io::Result<()> {
+        loop {
+            let next_held = self.held.keys().next().cloned();
+            let next_spilled = self.spilled.keys().next().cloned();
+            let (offset, bytes) = match (next_held, next_spilled) {
+                (Some(held), spilled) if held <= self.written &&
+                                         spilled.is_none_or(|spilled| held <= spilled) => {
+                    let bytes = self.held.remove(&held).unwrap_or_default();
+                    self.held_bytes -= bytes.len();
+                    (held, bytes)
+                }
+                (_, Some(spilled)) if spilled <= self.written => {
+                    let (position, length) = self.spilled.remove(&spilled).unwrap_or((0, 0));
+                    let mut bytes = vec![0; length];
+                    read_exact_at(self.spill_file()?, &mut bytes, position)?;
+                    (spilled, bytes)
+                }
+                _ => return Ok(()),
+            };
+
+            let skipped = (self.written - offset).min(bytes.len() as Bytes) as usize;
+            self.emit(&bytes[skipped..])?;
+        }
+    }
+
+    fn emit(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.writer.write_all(buf)?;
+        if let Some(ref mut verifier) = self.verifier {
+            verifier.update(buf);
+        }
+        self.written += buf.len() as Bytes;
+        Ok(())
+    }
+
+    /// Function to get the spill file, which is created the first time it is needed
+    fn spill_file(&mut self) -> io::Result<&File> {
+        if self.spill_file.is_none() {
+            let path = env::temp_dir().join(format!("snatch-{}-{}.spill",
+                                                    process::id(),
+                                                    SPILL_FILES.fetch_add(1, Ordering::SeqCst)));
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            self.spill_file = Some((path, file));
+        }
+        match self.spill_file {
+            Some((_, ref file)) => Ok(file),
+            None => unreachable!(),
+        }
+    }
+}
+
+impl<W: Write + Send> Drop for OrderedOutput<W> {
+    fn drop(&mut self) {
+        if let Some((ref path, _)) = self.spill_file {
+            let _ = remove_file(path);
+        }
+    }
+}
+
+impl<W: Write + Send> ChunkSink for OrderedSink<W> {
+    fn write_at(&self, offset: Bytes, buf: &[u8]) -> Result<(), SnatchError> {
+        self.output
+            .lock()
+            .map_err(|_| poisoned())?
+            .receive(offset, buf)?;
+        Ok(())
+    }
+
+    fn read_at(&self, _offset: Bytes, _buf: &mut [u8]) -> Result<(), SnatchError> {
+        Err(SnatchError::Io(io::Error::other("the bytes written to a stream cannot be read back")))
+    }
+
+    fn set_len(&self, length: Bytes) -> Result<(), SnatchError> {
+        self.output.lock().map_err(|_| poisoned())?.length = Some(length);
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), SnatchError> {
+        let mut output = self.output.lock().map_err(|_| poisoned())?;
+        output.writer.flush()?;
+        match output.length {
+            Some(length) if length != output.written => {
+                Err(SnatchError::LengthMismatch {
+                        expected: length,
+                        received: output.written,
+                    })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Function to get the path of the part file of the chunk at `index`
+pub fn part_file_path(part_path: &Path, index: usize) -> PathBuf {
+    let mut path = part_path.as_os_str().to_os_string();
+    path.push(format!(".{}", index));
+    PathBuf::from(path)
+}
+
+/// Function to get the chunks which have a part file: the given chunks, or a single chunk for
+/// the whole remote content if they are unknown
+fn part_chunks(chunks: &[ChunkState]) -> Vec<ChunkState> {
+    if chunks.is_empty() {
+        vec![ChunkState::new(0, Bytes::MAX)]
+    } else {
+        chunks.to_vec()
+    }
+}
+
+/// Function to find the indexes of the part files which belong to the given partial file, in
+/// order
+fn find_parts(part_path: &Path) -> io::Result<Vec<usize>> {
+    let directory = match part_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut prefix = part_path
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    prefix.push(".");
+    let prefix = prefix.to_string_lossy().into_owned();
+
+    let mut indexes = vec![];
+    for entry in read_dir(directory)? {
+        let file_name = entry?.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with(&prefix) {
+            if let Ok(index) = file_name[prefix.len()..].parse::<usize>() {
+                indexes.push(index);
+            }
+        }
+    }
+    indexes.sort();
+    Ok(indexes)
+}
+
+/// Function to reserve the blocks of the first `length` bytes of a file
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn allocate_file(file: &File, length: Bytes) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if length == 0 {
+        return Ok(());
+    }
+    match unsafe { ::libc::posix_fallocate(file.as_raw_fd(), 0, length as ::libc::off_t) } {
+        0 => Ok(()),
+        error => Err(io::Error::from_raw_os_error(error)),
+    }
+}
+
+/// Function to reserve the blocks of the first `length` bytes of a file, by writing its bytes
+/// again where the platform cannot reserve them: the holes of a sparse file are read as zeros,
+/// and the bytes beyond its end are written as zeros
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn allocate_file(file: &File, length: Bytes) -> io::Result<()> {
+    let file_length = file.metadata()?.len();
+    let mut buffer = vec![0; ALLOCATE_BUFFER_BYTES];
+    let mut offset = 0;
+    while offset < length {
+        let count = (length - offset).min(ALLOCATE_BUFFER_BYTES as Bytes) as usize;
+        let read = file_length.saturating_sub(offset).min(count as Bytes) as usize;
+        let block = &mut buffer[..count];
+        read_exact_at(file, &mut block[..read], offset)?;
+        block[read..].fill(0);
+        write_all_at(file, block, offset)?;
+        offset += count as Bytes;
+    }
+    Ok(())
+}
+
+/// Function to map the whole content of a file, if it is not empty
+fn map_file(file: &File) -> io::Result<Option<MmapRaw>> {
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    MmapRaw::map_raw(file).map(Some)
+}
+
+fn poisoned() -> io::Error {
+    io::Error::other("the output storage is poisoned")
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: Bytes) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: Bytes) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: Bytes) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write")),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as Bytes;
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: Bytes) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to read")),
+            Ok(n) => {
+                let tmp = buf;
+                buf = &mut tmp[n..];
+                offset += n as Bytes;
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_sink {
+
+    use checksum::{Checksum, ChecksumAlgorithm};
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use state::ChunkState;
+    use state::test_state::new_state;
+    use std::path::PathBuf;
+    use super::{ChunkSink, MemorySink, MmapSink, OrderedSink, PartFilesSink, Prealloc,
+                part_file_path};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(name)
+    }
+
+    /// Write two chunks out of order, and one byte over the first one
+    fn write_chunks(sink: &dyn ChunkSink) {
+        sink.set_len(8).unwrap();
+        sink.write_at(4, b"efgh").unwrap();
+        sink.write_at(0, b"abcd").unwrap();
+        sink.write_at(3, b"D").unwrap();
+        let mut content = [0; 8];
+        sink.read_at(0, &mut content).unwrap();
+        assert_eq!(b"abcDefgh", &content);
+    }
+
+    #[test]
+    fn prealloc_should_be_parsed_from_its_name() {
+        assert_eq!(Some(Prealloc::None), Prealloc::from_name("none"));
+        assert_eq!(Some(Prealloc::Full), Prealloc::from_name("FULL"));
+        assert_eq!(None, Prealloc::from_name("dense"));
+    }
+
+    #[test]
+    fn memory_sink_should_keep_the_content() {
+        let sink = MemorySink::new();
+        write_chunks(&sink);
+        sink.set_len(6).unwrap();
+        assert_eq!(b"abcDef".to_vec(), sink.content().unwrap());
+        assert!(sink.read_at(4, &mut [0; 4]).is_err());
+    }
+
+    #[test]
+    fn mmap_sink_should_write_in_the_file() {
+        let path = temp_path("snatch_test_mmap_sink.bin");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let sink = MmapSink::new(file).unwrap();
+        write_chunks(&sink);
+        assert!(sink.write_at(6, b"ijk").is_err());
+        sink.finish().unwrap();
+        assert_eq!(b"abcDefgh".to_vec(), fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn mmap_sink_should_reserve_the_blocks_of_the_file() {
+        use std::os::unix::fs::MetadataExt;
+        let path = temp_path("snatch_test_mmap_sink_blocks.bin");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let sink = MmapSink::new(file).unwrap();
+        sink.set_len(1024 * 1024).unwrap();
+        assert!(fs::metadata(&path).unwrap().blocks() * 512 >= 1024 * 1024);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn part_files_should_be_concatenated() {
+        let path = temp_path("snatch_test_part_files.bin.part");
+        let chunks = vec![ChunkState::new(0, 3), ChunkState::new(4, 7)];
+        let sink = PartFilesSink::create(&path, &chunks).unwrap();
+        write_chunks(&sink);
+        assert_eq!(b"abcD".to_vec(), fs::read(part_file_path(&path, 0)).unwrap());
+        assert_eq!(b"efgh".to_vec(), fs::read(part_file_path(&path, 1)).unwrap());
+
+        // The part files are found again to resume the download
+        let sink = PartFilesSink::open(&path, &chunks).unwrap();
+        sink.set_len(8).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(b"abcDefgh".to_vec(), fs::read(&path).unwrap());
+        assert!(!part_file_path(&path, 0).exists());
+        assert!(PartFilesSink::open(&path, &chunks).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn split_chunk_should_get_its_own_part_file() {
+        let path = temp_path("snatch_test_split_part_files.bin.part");
+        let mut state = new_state(8, 1);
+        let sink = PartFilesSink::create(&path, &state.chunks).unwrap();
+        sink.write_at(0, b"ab").unwrap();
+        state.chunks[0].written = 2;
+        assert_eq!(Some(1), state.split_chunk(0, 1));
+        sink.add_chunk(1, &state.chunks[1]).unwrap();
+        sink.write_at(5, b"fgh").unwrap();
+        sink.write_at(2, b"cde").unwrap();
+        // Each part file is written from its beginning, without any hole
+        assert_eq!(b"abcde".to_vec(), fs::read(part_file_path(&path, 0)).unwrap());
+        assert_eq!(b"fgh".to_vec(), fs::read(part_file_path(&path, 1)).unwrap());
+
+        // A piece downloaded again gets its own part file, which takes over its bytes
+        state.add_chunk(3, 5);
+        sink.add_chunk(2, &state.chunks[2]).unwrap();
+        sink.write_at(3, b"DEF").unwrap();
+        assert_eq!(b"abcde".to_vec(), fs::read(part_file_path(&path, 0)).unwrap());
+        assert_eq!(b"DEF".to_vec(), fs::read(part_file_path(&path, 2)).unwrap());
+        let mut content = [0; 8];
+        sink.read_at(0, &mut content).unwrap();
+        assert_eq!(b"abcDEFgh", &content);
+
+        // The part files of the chunks which are not in the state are removed on resume
+        state.chunks.truncate(2);
+        let sink = PartFilesSink::open(&path, &state.chunks).unwrap();
+        assert!(!part_file_path(&path, 2).exists());
+        sink.set_len(8).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(b"abcdefgh".to_vec(), fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn preallocated_part_files_should_have_the_length_of_their_chunk() {
+        let path = temp_path("snatch_test_preallocated_parts.bin.part");
+        let chunks = vec![ChunkState::new(0, 3), ChunkState::new(4, 7)];
+        let sink = PartFilesSink::create(&path, &chunks).unwrap();
+        sink.set_len(6).unwrap();
+        sink.preallocate(6).unwrap();
+        assert!(PartFilesSink::exist(&path));
+        assert_eq!(4, fs::metadata(part_file_path(&path, 0)).unwrap().len());
+        assert_eq!(2, fs::metadata(part_file_path(&path, 1)).unwrap().len());
+
+        sink.write_at(0, b"abcdef").unwrap();
+        sink.finish().unwrap();
+        assert!(!PartFilesSink::exist(&path));
+        assert_eq!(b"abcdef".to_vec(), fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ordered_sink_should_write_the_chunks_in_order() {
+        let mut stream = vec![];
+        {
+            let checksum = Checksum::compute(ChecksumAlgorithm::Md5, b"abcdefghij");
+            let sink = OrderedSink::new(&mut stream, 4).with_checksums(&[checksum]);
+            sink.set_len(10).unwrap();
+            // Held in memory, then in the spill file once the reorder buffer is full
+            sink.write_at(6, b"ghij").unwrap();
+            sink.write_at(3, b"def").unwrap();
+            sink.write_at(0, b"abc").unwrap();
+            // Already written
+            sink.write_at(0, b"ab").unwrap();
+            sink.finish().unwrap();
+            sink.verify().unwrap();
+        }
+        assert_eq!(b"abcdefghij".to_vec(), stream);
+
+        let sink = OrderedSink::new(vec![], 4);
+        sink.set_len(10).unwrap();
+        sink.write_at(4, b"efgh").unwrap();
+        assert!(sink.finish().is_err());
+    }
+
+}
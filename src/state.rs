@@ -0,0 +1,438 @@
+use Bytes;
+use cargo_helper::CargoInfo;
+use download::split_in_chunks;
+use error::SnatchError;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs::{File, remove_file, rename};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Extension appended to the local file name to get the path of its state file
+pub const STATE_FILE_EXTENSION: &str = "snatch";
+
+/// Extension appended to the local file name to get the path of the partial file the remote
+/// content is downloaded into
+pub const PART_FILE_EXTENSION: &str = "part";
+
+/// Constant to represent the minimal interval (in milliseconds) between two
+/// writes of the state file on the disk
+const STATE_SAVE_INTERVAL_MILLIS: u64 = 1000;
+
+/// Represents the progress of a chunk: its (inclusive) bounds in the remote content,
+/// and the number of bytes already written to the local file from its beginning
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkState {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub written: Bytes,
+}
+
+impl ChunkState {
+    pub fn new(start: Bytes, end: Bytes) -> ChunkState {
+        ChunkState {
+            start,
+            end,
+            written: 0,
+        }
+    }
+
+    /// Get the total length of the chunk
+    pub fn length(&self) -> Bytes {
+        self.end - self.start + 1
+    }
+
+    /// Get the number of bytes which remain to download for this chunk
+    pub fn remaining(&self) -> Bytes {
+        self.length().saturating_sub(self.written)
+    }
+
+    /// Get the offset of the next byte to download for this chunk
+    pub fn next_offset(&self) -> Bytes {
+        self.start + self.written
+    }
+
+    /// Check if every byte of the chunk has been written to the local file
+    pub fn is_complete(&self) -> bool {
+        self.written >= self.length()
+    }
+}
+
+/// Represents everything needed to resume a download: the remote content it belongs to,
+/// and the progress of each of its chunks
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadState {
+    pub url: String,
+    pub content_length: Bytes,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub chunks: Vec<ChunkState>,
+}
+
+impl DownloadState {
+    /// Create a new state, splitting the remote content into `nb_chunks` chunks.
+    /// The length of the remote content must be known - else, it has no chunk at all.
+    pub fn new(url: &str, cargo_info: &CargoInfo, nb_chunks: u64) -> DownloadState {
+        let content_length = cargo_info.content_length.unwrap_or(0);
+        DownloadState {
+            url: String::from(url),
+            content_length,
+            etag: cargo_info.etag.clone(),
+            last_modified: cargo_info.last_modified.clone(),
+            chunks: split_in_chunks(content_length, nb_chunks),
+        }
+    }
+
+    /// Get the path of the state file that belongs to the given local file
+    pub fn state_path(local_path: &Path) -> PathBuf {
+        append_extension(local_path, STATE_FILE_EXTENSION)
+    }
+
+    /// Get the path of the partial file that belongs to the given local file: the remote content
+    /// is downloaded into it, and it replaces the local file once complete.
+    /// It is in the same directory as the local file, so it can be renamed atomically.
+    pub fn part_path(local_path: &Path) -> PathBuf {
+        append_extension(local_path, PART_FILE_EXTENSION)
+    }
+
+    /// Check if this state describes the same remote content as the given one.
+    /// A state without any validator cannot be trusted, and never matches.
+    pub fn matches(&self, url: &str, cargo_info: &CargoInfo) -> bool {
+        if self.etag.is_none() && self.last_modified.is_none() {
+            return false;
+        }
+        self.url == url && Some(self.content_length) == cargo_info.content_length &&
+        self.etag == cargo_info.etag && self.last_modified == cargo_info.last_modified
+    }
+
+    /// Split the remaining part of the chunk `chunk_index` in two halves, and add the second
+    /// one as a new chunk.
+    /// The chunk is split only if each half contains at least `min_length` bytes.
+    /// This function returns the index of the new chunk.
+    pub fn split_chunk(&mut self, chunk_index: usize, min_length: Bytes) -> Option<usize> {
+        let (next_offset, end) = {
+            let chunk = &self.chunks[chunk_index];
+            if chunk.remaining() < 2 * min_length.max(1) {
+                return None;
+            }
+            (chunk.next_offset(), chunk.end)
+        };
+
+        let new_end = next_offset + (end - next_offset).div_ceil(2) - 1;
+        self.chunks[chunk_index].end = new_end;
+        self.chunks.push(ChunkState::new(new_end + 1, end));
+        Some(self.chunks.len() - 1)
+    }
+
+    /// Add a new chunk to download, from `start` to `end` (inclusive), even if these bytes are
+    /// already written by another chunk.
+    /// This function returns the index of the new chunk.
+    pub fn add_chunk(&mut self, start: Bytes, end: Bytes) -> usize {
+        self.chunks.push(ChunkState::new(start, end));
+        self.chunks.len() - 1
+    }
+
+    /// Get the number of bytes already written to the local file
+    pub fn downloaded_bytes(&self) -> Bytes {
+        self.chunks.iter().map(|chunk| chunk.written).sum()
+    }
+
+    /// Load a state from the given state file
+    pub fn load(path: &Path) -> Result<DownloadState, SnatchError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut state = DownloadState {
+            url: String::new(),
+            content_length: 0,
+            etag: None,
+            last_modified: None,
+            chunks: vec![],
+        };
+
+        for line in reader.lines() {
+            let line = line?;
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+            match key {
+                "url" => state.url = String::from(value),
+                "content_length" => state.content_length = parse_bytes(value)?,
+                "etag" => state.etag = Some(String::from(value)),
+                "last_modified" => state.last_modified = Some(String::from(value)),
+                "chunk" => {
+                    let values = value
+                        .split(' ')
+                        .map(parse_bytes)
+                        .collect::<Result<Vec<Bytes>, SnatchError>>()?;
+                    if values.len() != 3 || values[0] > values[1] {
+                        return Err(invalid_data(&format!("Invalid chunk line: {}", line)));
+                    }
+                    state.chunks.push(ChunkState {
+                                          start: values[0],
+                                          end: values[1],
+                                          written: values[2],
+                                      });
+                }
+                "" => {}
+                _ => return Err(invalid_data(&format!("Unknown key in state file: {}", key))),
+            }
+        }
+
+        if state.url.is_empty() || state.chunks.is_empty() {
+            return Err(invalid_data("Incomplete state file"));
+        }
+        Ok(state)
+    }
+
+    /// Save the state to the given state file.
+    /// The content is written to a temporary file first, and then renamed, to never leave
+    /// a truncated state file behind.
+    pub fn save(&self, path: &Path) -> Result<(), SnatchError> {
+        let mut tmp_name = path.as_os_str().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        {
+            let mut tmp_file = File::create(&tmp_path)?;
+            tmp_file.write_all(self.to_string().as_bytes())?;
+            tmp_file.sync_all()?;
+        }
+        rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+impl Display for DownloadState {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "url {}\ncontent_length {}", self.url, self.content_length)?;
+        if let Some(ref etag) = self.etag {
+            writeln!(f, "etag {}", etag)?;
+        }
+        if let Some(ref last_modified) = self.last_modified {
+            writeln!(f, "last_modified {}", last_modified)?;
+        }
+        for chunk in &self.chunks {
+            writeln!(f, "chunk {} {} {}", chunk.start, chunk.end, chunk.written)?;
+        }
+        Ok(())
+    }
+}
+
+/// Structure that shares a download state between the chunk threads, and periodically
+/// writes it to its state file.
+/// The state file is written out of the lock of the state, so the chunk threads never wait for
+/// the disk.
+#[derive(Clone)]
+pub struct StateTracker {
+    inner: Arc<Mutex<TrackedState>>,
+    /// Held while the state file is written, so the saves never overlap
+    save_lock: Arc<Mutex<()>>,
+}
+
+struct TrackedState {
+    state: DownloadState,
+    /// The state file, if the download can be resumed
+    path: Option<PathBuf>,
+    last_save: Instant,
+}
+
+impl StateTracker {
+    pub fn new(state: DownloadState, path: PathBuf) -> StateTracker {
+        StateTracker::with_path(state, Some(path))
+    }
+
+    /// Create a tracker which never saves the state, for a download which cannot be resumed
+    pub fn in_memory(state: DownloadState) -> StateTracker {
+        StateTracker::with_path(state, None)
+    }
+
+    fn with_path(state: DownloadState, path: Option<PathBuf>) -> StateTracker {
+        StateTracker {
+            inner: Arc::new(Mutex::new(TrackedState {
+                                           state,
+                                           path,
+                                           last_save: Instant::now(),
+                                       })),
+            save_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Get a copy of the current chunks progress
+    pub fn chunks(&self) -> Vec<ChunkState> {
+        self.inner.lock().unwrap().state.chunks.clone()
+    }
+
+    /// Get a copy of the current progress of the chunk `chunk_index`
+    pub fn chunk(&self, chunk_index: usize) -> ChunkState {
+        self.inner.lock().unwrap().state.chunks[chunk_index].clone()
+    }
+
+    /// Split the remaining part of the chunk `chunk_index` in two halves.
+    /// See `DownloadState::split_chunk`.
+    pub fn split_chunk(&self, chunk_index: usize, min_length: Bytes) -> Option<usize> {
+        self.inner
+            .lock()
+            .unwrap()
+            .state
+            .split_chunk(chunk_index, min_length)
+    }
+
+    /// Add a new chunk to download. See `DownloadState::add_chunk`.
+    pub fn add_chunk(&self, start: Bytes, end: Bytes) -> usize {
+        self.inner.lock().unwrap().state.add_chunk(start, end)
+    }
+
+    /// Record that `written` bytes of the chunk `chunk_index` are on the disk.
+    /// The state file is updated if the last save is old enough, and if no other thread is
+    /// saving it.
+    pub fn update(&self, chunk_index: usize, written: Bytes) {
+        let save_due = {
+            let mut tracked = self.inner.lock().unwrap();
+            tracked.state.chunks[chunk_index].written = written;
+            let save_interval = Duration::from_millis(STATE_SAVE_INTERVAL_MILLIS);
+            tracked.path.is_some() &&
+            Instant::now().duration_since(tracked.last_save) > save_interval
+        };
+
+        if save_due {
+            if let Ok(_save_guard) = self.save_lock.try_lock() {
+                if self.save_snapshot().is_err() {
+                    warning!("Cannot save the download state file!");
+                }
+            }
+        }
+    }
+
+    /// Write the current state to the state file
+    pub fn save(&self) -> Result<(), SnatchError> {
+        let _save_guard = self.save_lock.lock().unwrap();
+        self.save_snapshot()
+    }
+
+    /// Delete the state file, once the download is over
+    pub fn remove(&self) -> Result<(), SnatchError> {
+        let _save_guard = self.save_lock.lock().unwrap();
+        let tracked = self.inner.lock().unwrap();
+        if let Some(ref path) = tracked.path {
+            remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Write a copy of the current state to the state file, out of the lock of the state.
+    /// The save lock must be held, so the latest copy is always written last.
+    fn save_snapshot(&self) -> Result<(), SnatchError> {
+        let (state, path) = {
+            let mut tracked = self.inner.lock().unwrap();
+            tracked.last_save = Instant::now();
+            match tracked.path {
+                Some(ref path) => (tracked.state.clone(), path.clone()),
+                None => return Ok(()),
+            }
+        };
+        state.save(&path)
+    }
+}
+
+/// Function to get the path of a file named after the given file, followed by an extension
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
+}
+
+fn parse_bytes(value: &str) -> Result<Bytes, SnatchError> {
+    value
+        .parse::<Bytes>()
+        .map_err(|_| invalid_data(&format!("Invalid number in state file: {}", value)))
+}
+
+fn invalid_data(message: &str) -> SnatchError {
+    SnatchError::Io(io::Error::new(io::ErrorKind::InvalidData, message.to_owned()))
+}
+
+#[cfg(test)]
+pub mod test_state {
+
+    use download::split_in_chunks;
+    use super::{ChunkState, DownloadState, StateTracker};
+    use std::env::temp_dir;
+    use std::path::Path;
+
+    /// Build the state of a remote content without any validator, split into `nb_chunks` chunks
+    pub fn new_state(content_length: u64, nb_chunks: u64) -> DownloadState {
+        DownloadState {
+            url: String::from("http://localhost/file.zip"),
+            content_length,
+            etag: None,
+            last_modified: None,
+            chunks: split_in_chunks(content_length, nb_chunks),
+        }
+    }
+
+    #[test]
+    fn state_and_part_paths_should_append_their_extension() {
+        assert_eq!(Path::new("/tmp/file.zip.snatch"),
+                   DownloadState::state_path(Path::new("/tmp/file.zip")).as_path());
+        assert_eq!(Path::new("/tmp/file.zip.part"),
+                   DownloadState::part_path(Path::new("/tmp/file.zip")).as_path());
+    }
+
+    #[test]
+    fn saved_state_should_be_loaded_back() {
+        let mut state = new_state(1000, 2);
+        state.etag = Some(String::from("\"abc\""));
+        state.last_modified = Some(String::from("Sat, 29 Oct 1994 19:43:31 GMT"));
+        state.chunks[1].written = 42;
+
+        let path = temp_dir().join("snatch_saved_state_should_be_loaded_back.snatch");
+        state.save(&path).unwrap();
+        assert_eq!(state, DownloadState::load(&path).unwrap());
+    }
+
+    #[test]
+    fn tracker_should_save_the_latest_progress() {
+        let state = new_state(1000, 2);
+        let path = temp_dir().join("snatch_tracker_should_save_the_latest_progress.snatch");
+        let tracker = StateTracker::new(state, path.clone());
+        tracker.update(1, 42);
+        tracker.save().unwrap();
+        assert_eq!(42, DownloadState::load(&path).unwrap().chunks[1].written);
+        tracker.remove().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn split_chunk_should_give_the_second_half_of_the_remaining_bytes() {
+        let mut state = new_state(1000, 1);
+        state.chunks[0].written = 200;
+
+        assert_eq!(Some(1), state.split_chunk(0, 100));
+        assert_eq!(ChunkState { start: 0, end: 599, written: 200 }, state.chunks[0]);
+        assert_eq!(ChunkState::new(600, 999), state.chunks[1]);
+    }
+
+    #[test]
+    fn split_chunk_should_not_create_too_small_chunks() {
+        let mut state = new_state(1000, 1);
+        state.chunks[0].written = 900;
+
+        assert_eq!(None, state.split_chunk(0, 60));
+        assert_eq!(1, state.chunks.len());
+    }
+
+    #[test]
+    fn chunk_state_should_know_when_it_is_complete() {
+        let mut chunk = ChunkState::new(10, 19);
+        assert!(!chunk.is_complete());
+        chunk.written = 10;
+        assert!(chunk.is_complete());
+        assert_eq!(20, chunk.next_offset());
+    }
+
+}
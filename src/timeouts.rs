@@ -0,0 +1,207 @@
+use Bytes;
+use error::SnatchError;
+use std::time::{Duration, Instant};
+
+/// Constant to represent the default maximum time (in seconds) to connect to a remote server
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 30;
+
+/// Constant to represent the default maximum time (in seconds) to wait for some bytes from a
+/// connection
+pub const DEFAULT_READ_TIMEOUT_SECS: u64 = 60;
+
+/// Constant to represent the default time (in seconds) a connection may stay under the minimum
+/// speed
+pub const DEFAULT_MIN_SPEED_SECS: u64 = 30;
+
+/// Structure that contains the lowest throughput of a connection, and how long a connection may
+/// stay under it before being aborted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MinSpeed {
+    /// The minimum speed, in bytes per second
+    pub speed: Bytes,
+    pub time: Duration,
+}
+
+/// Structure that contains the timeouts of the connections to the remote servers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeouts {
+    /// The maximum time to connect to a remote server
+    pub connect: Option<Duration>,
+    /// The maximum time to wait for some bytes from a connection, or to send a request
+    pub read: Option<Duration>,
+    /// The minimum speed of a connection, which is aborted if it stays slower
+    pub min_speed: Option<MinSpeed>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: Some(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS)),
+            read: Some(Duration::from_secs(DEFAULT_READ_TIMEOUT_SECS)),
+            min_speed: None,
+        }
+    }
+}
+
+impl Timeouts {
+    /// Get how long a connection may receive nothing before being aborted: the read timeout, or
+    /// the minimum speed time if it is shorter, since such a connection is too slow anyway
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        let min_speed_time = self.min_speed.map(|min_speed| min_speed.time);
+        match (self.read, min_speed_time) {
+            (Some(read), Some(min_speed_time)) => Some(read.min(min_speed_time)),
+            (read, min_speed_time) => read.or(min_speed_time),
+        }
+    }
+}
+
+/// Structure that watches the throughput of a connection, over consecutive periods of the
+/// minimum speed time
+pub struct SpeedWatchdog {
+    min_speed: Option<MinSpeed>,
+    period_start: Instant,
+    period_bytes: Bytes,
+}
+
+impl SpeedWatchdog {
+    /// Create a new watchdog for a connection which has just been opened
+    pub fn new(min_speed: Option<MinSpeed>) -> SpeedWatchdog {
+        SpeedWatchdog {
+            min_speed,
+            period_start: Instant::now(),
+            period_bytes: 0,
+        }
+    }
+
+    /// Function to report that the connection has received `bytes` bytes.
+    /// This function returns an error if the connection has been slower than the minimum speed
+    /// during the whole last period.
+    pub fn check(&mut self, bytes: Bytes) -> Result<(), SnatchError> {
+        self.check_at(bytes, Instant::now())
+    }
+
+    /// Function to report that the connection has been paused on purpose (e.g. by the bandwidth
+    /// limits) during `duration`, which does not count in its throughput
+    pub fn pause(&mut self, duration: Duration) {
+        self.period_start += duration;
+    }
+
+    fn check_at(&mut self, bytes: Bytes, now: Instant) -> Result<(), SnatchError> {
+        let min_speed = match self.min_speed {
+            Some(min_speed) => min_speed,
+            None => return Ok(()),
+        };
+        self.period_bytes += bytes;
+
+        let elapsed = now.saturating_duration_since(self.period_start);
+        if elapsed < min_speed.time {
+            return Ok(());
+        }
+        let elapsed_millis = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+        let speed = self.period_bytes * 1000 / elapsed_millis.max(1);
+        if speed < min_speed.speed {
+            return Err(SnatchError::TooSlow {
+                           speed,
+                           min_speed: min_speed.speed,
+                       });
+        }
+        self.period_start = now;
+        self.period_bytes = 0;
+        Ok(())
+    }
+}
+
+/// Function to parse a duration given in seconds, or using a unit (e.g. `500ms`, `30s`, `5m`
+/// or `1h`)
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let unit_index = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(unit_index);
+    let value = value.parse::<u64>().ok()?;
+
+    match unit.trim().to_lowercase().as_str() {
+        "ms" => Some(Duration::from_millis(value)),
+        "" | "s" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_secs(value * 60)),
+        "h" => Some(Duration::from_secs(value * 3600)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_timeouts {
+
+    use error::SnatchError;
+    use std::time::{Duration, Instant};
+    use super::{DEFAULT_READ_TIMEOUT_SECS, MinSpeed, SpeedWatchdog, Timeouts, parse_duration};
+
+    fn watchdog() -> SpeedWatchdog {
+        SpeedWatchdog::new(Some(MinSpeed {
+                                    speed: 1000,
+                                    time: Duration::from_secs(10),
+                                }))
+    }
+
+    #[test]
+    fn duration_should_accept_units() {
+        assert_eq!(Some(Duration::from_secs(30)), parse_duration("30"));
+        assert_eq!(Some(Duration::from_secs(30)), parse_duration("30s"));
+        assert_eq!(Some(Duration::from_millis(500)), parse_duration("500ms"));
+        assert_eq!(Some(Duration::from_secs(300)), parse_duration("5M"));
+        assert_eq!(Some(Duration::from_secs(3600)), parse_duration("1h"));
+        assert_eq!(None, parse_duration("s"));
+        assert_eq!(None, parse_duration("-1s"));
+        assert_eq!(None, parse_duration("10 days"));
+    }
+
+    #[test]
+    fn idle_timeout_should_be_the_shortest_one() {
+        let mut timeouts = Timeouts::default();
+        assert_eq!(Some(Duration::from_secs(DEFAULT_READ_TIMEOUT_SECS)),
+                   timeouts.idle_timeout());
+        timeouts.min_speed = Some(MinSpeed {
+                                      speed: 1000,
+                                      time: Duration::from_secs(10),
+                                  });
+        assert_eq!(Some(Duration::from_secs(10)), timeouts.idle_timeout());
+        timeouts.read = None;
+        assert_eq!(Some(Duration::from_secs(10)), timeouts.idle_timeout());
+    }
+
+    #[test]
+    fn slow_connection_should_be_aborted_after_the_whole_period() {
+        let mut watchdog = watchdog();
+        let start = watchdog.period_start;
+        assert!(watchdog.check_at(500, start + Duration::from_secs(5)).is_ok());
+        match watchdog.check_at(500, start + Duration::from_secs(10)) {
+            Err(SnatchError::TooSlow { speed, min_speed }) => {
+                assert_eq!(100, speed);
+                assert_eq!(1000, min_speed);
+            }
+            _ => panic!("the connection should be too slow"),
+        }
+    }
+
+    #[test]
+    fn fast_connection_should_start_a_new_period() {
+        let mut watchdog = watchdog();
+        let start = watchdog.period_start;
+        assert!(watchdog.check_at(20000, start + Duration::from_secs(10)).is_ok());
+        assert!(watchdog.check_at(0, start + Duration::from_secs(15)).is_ok());
+        assert!(watchdog.check_at(0, start + Duration::from_secs(20)).is_err());
+    }
+
+    #[test]
+    fn pause_should_not_count_in_the_throughput() {
+        let mut watchdog = watchdog();
+        let start = watchdog.period_start;
+        watchdog.pause(Duration::from_secs(5));
+        assert!(watchdog.check_at(10000, start + Duration::from_secs(15)).is_ok());
+        assert!(SpeedWatchdog::new(None)
+                    .check_at(0, Instant::now() + Duration::from_secs(3600))
+                    .is_ok());
+    }
+
+}
@@ -1,14 +1,115 @@
+use Bytes;
+use error::SnatchError;
 use std::io;
 use std::io::Write;
+use std::path::Path;
 
-pub fn prompt_user(prompt: &str) -> String {
+/// Function to ask something to the user, and to get its answer.
+/// If the standard input is closed, the user has cancelled the prompt.
+pub fn prompt_user(prompt: &str) -> Result<String, SnatchError> {
     warning!(prompt);
-    io::stdout().flush().expect("Couldn't flush stdout!");
+    io::stdout().flush()?;
+    read_user_input()
+}
 
+/// Function to ask a secret to the user, without echoing its answer in the terminal
+pub fn prompt_password(prompt: &str) -> Result<String, SnatchError> {
+    warning!(prompt);
+    io::stdout().flush()?;
+
+    let _echo_guard = EchoGuard::disable_echo();
+    let user_input = read_user_input();
+    ::logs::print_line("");
+    user_input
+}
+
+fn read_user_input() -> Result<String, SnatchError> {
     let mut user_input = String::new();
-    io::stdin()
-        .read_line(&mut user_input)
-        .ok()
-        .expect("Couldn't read line!");
-    String::from(user_input.trim())
-}
\ No newline at end of file
+    if io::stdin().read_line(&mut user_input)? == 0 {
+        return Err(SnatchError::Cancelled);
+    }
+    Ok(String::from(user_input.trim()))
+}
+
+/// Structure that disables the echo of the terminal, and restores it when dropped
+#[cfg(unix)]
+struct EchoGuard {
+    original_termios: Option<::libc::termios>,
+}
+
+#[cfg(unix)]
+impl EchoGuard {
+    fn disable_echo() -> EchoGuard {
+        use libc::{ECHO, STDIN_FILENO, TCSANOW, tcgetattr, tcsetattr, termios};
+        use std::mem;
+
+        let mut termios: termios = unsafe { mem::zeroed() };
+        // The standard input is not a terminal, so there is nothing to hide
+        if unsafe { tcgetattr(STDIN_FILENO, &mut termios) } != 0 {
+            return EchoGuard { original_termios: None };
+        }
+        let original_termios = termios;
+        termios.c_lflag &= !ECHO;
+        unsafe { tcsetattr(STDIN_FILENO, TCSANOW, &termios) };
+        EchoGuard { original_termios: Some(original_termios) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EchoGuard {
+    fn drop(&mut self) {
+        if let Some(ref original_termios) = self.original_termios {
+            unsafe { ::libc::tcsetattr(::libc::STDIN_FILENO, ::libc::TCSANOW, original_termios) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct EchoGuard;
+
+#[cfg(not(unix))]
+impl EchoGuard {
+    fn disable_echo() -> EchoGuard {
+        EchoGuard
+    }
+}
+
+/// Function to get the free space (in bytes) that the current user can use on the file system of
+/// the given path.
+/// None is returned if the platform does not tell it.
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> io::Result<Option<Bytes>> {
+    use std::ffi::CString;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid path"))?;
+    let mut stat: ::libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { ::libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(stat.f_bavail as Bytes * stat.f_frsize as Bytes))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> io::Result<Option<Bytes>> {
+    Ok(None)
+}
+
+/// Function to get the lowercase hexadecimal representation of some bytes
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Function to get the bytes of an hexadecimal string, whatever its case, or None if this string
+/// is not valid hexadecimal
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
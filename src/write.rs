@@ -1,47 +1,177 @@
+use error::SnatchError;
+use sink::{ChunkSink, FileSink};
+use state::ChunkState;
 use std::fs::File;
-use std::io::Write;
-use std::io::{Seek, SeekFrom};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-/// Structure that contains a shared file instance
+/// Constant to represent the default length of the buffer of each chunk writer, which gathers the
+/// received bytes to write them in larger blocks.
+/// Added to the length of the download buffer, this length must be lower than the minimal length
+/// of the halves of a split chunk, so the buffered bytes never go beyond the new end of a chunk.
+pub const WRITE_BUFFER_BYTES: usize = 512 * 1024;
+
+/// Structure that contains a shared storage instance - a file by default.
+/// The storage is written without a lock, so the workers never wait for each other.
 pub struct OutputFileWriter {
-    file: Arc<Mutex<File>>,
+    sink: Arc<dyn ChunkSink>,
+    write_buffer_bytes: usize,
 }
 
-/// Structure that contains a shared file instance and the current
-/// offset of this file
+/// Structure that contains a shared storage instance and the current
+/// offset of this storage, and the bytes which are not written yet
 pub struct OutputChunkWriter {
     output: OutputFileWriter,
     offset: u64,
+    /// The offset (from the start of the chunk) of the first buffered byte
+    buffer_offset: u64,
+    buffer: Vec<u8>,
 }
 
 impl Clone for OutputFileWriter {
     fn clone(&self) -> OutputFileWriter {
-        OutputFileWriter { file: self.file.clone() }
+        OutputFileWriter {
+            sink: self.sink.clone(),
+            write_buffer_bytes: self.write_buffer_bytes,
+        }
     }
 }
 
 impl OutputFileWriter {
-    pub fn write(&mut self, offset: u64, buf: &[u8]) {
-        let mut out_file = self.file.lock().unwrap();
-        out_file.seek(SeekFrom::Start(offset)).expect("Error while seeking in file.");
-        out_file.write_all(buf).expect("Error while writing to file.");
+    pub fn write(&self, offset: u64, buf: &[u8]) -> Result<(), SnatchError> {
+        self.sink.write_at(offset, buf)
     }
 
-    pub fn get_chunk_writer(&mut self, offset: u64) -> OutputChunkWriter {
+    /// Read back some bytes of the storage, from the given offset.
+    /// A file must have been opened for reading too.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), SnatchError> {
+        self.sink.read_at(offset, buf)
+    }
+
+    /// Set the length of the storage, truncating it or extending it with zeros
+    pub fn set_len(&self, length: u64) -> Result<(), SnatchError> {
+        self.sink.set_len(length)
+    }
+
+    /// Reserve the blocks of the storage for `length` bytes, before anything is written
+    pub fn preallocate(&self, length: u64) -> Result<(), SnatchError> {
+        self.sink.preallocate(length)
+    }
+
+    /// Give its own place in the storage to the chunk at `index`, if the storage has one for
+    /// each chunk, before it is written
+    pub fn add_chunk(&self, index: usize, chunk: &ChunkState) -> Result<(), SnatchError> {
+        self.sink.add_chunk(index, chunk)
+    }
+
+    /// Write the content of the storage to the disk, in its final place, so it is not lost in
+    /// case of a system crash
+    pub fn finish(&self) -> Result<(), SnatchError> {
+        self.sink.finish()
+    }
+
+    pub fn get_chunk_writer(&self, offset: u64) -> OutputChunkWriter {
         OutputChunkWriter {
             output: self.clone(),
             offset: offset,
+            buffer_offset: 0,
+            buffer: Vec::with_capacity(self.write_buffer_bytes),
         }
     }
 
+    /// Set the length of the buffer of the chunk writers, 0 to write the bytes at once
+    pub fn with_write_buffer(mut self, write_buffer_bytes: usize) -> OutputFileWriter {
+        self.write_buffer_bytes = write_buffer_bytes;
+        self
+    }
+
     pub fn new(file: File) -> OutputFileWriter {
-        OutputFileWriter { file: Arc::new(Mutex::new(file)) }
+        OutputFileWriter::with_sink(Arc::new(FileSink::new(file)))
+    }
+
+    pub fn with_sink(sink: Arc<dyn ChunkSink>) -> OutputFileWriter {
+        OutputFileWriter {
+            sink,
+            write_buffer_bytes: WRITE_BUFFER_BYTES,
+        }
     }
 }
 
 impl OutputChunkWriter {
-    pub fn write(&mut self, done_offset: u64, buf: &[u8]) {
-        self.output.write(self.offset + done_offset, buf)
+    /// Write some bytes at the given offset from the start of the chunk.
+    /// The bytes may be kept in the buffer until it is full, or until the writer is flushed.
+    pub fn write(&mut self, done_offset: u64, buf: &[u8]) -> Result<(), SnatchError> {
+        // Only the contiguous bytes are gathered
+        if !self.buffer.is_empty() &&
+           (done_offset != self.buffer_offset + self.buffer.len() as u64 ||
+            self.buffer.len() + buf.len() > self.buffer.capacity()) {
+            self.flush()?;
+        }
+        if buf.len() > self.buffer.capacity() {
+            return self.output.write(self.offset + done_offset, buf);
+        }
+        if self.buffer.is_empty() {
+            self.buffer_offset = done_offset;
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(())
+    }
+
+    /// Function to get the number of bytes which are not written in the file yet
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Function to write the buffered bytes in the file.
+    /// The buffered bytes are dropped even if they cannot be written.
+    pub fn flush(&mut self) -> Result<(), SnatchError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let result = self.output
+            .write(self.offset + self.buffer_offset, &self.buffer);
+        self.buffer.clear();
+        result
     }
 }
+
+#[cfg(test)]
+mod test_write {
+
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use super::OutputFileWriter;
+
+    #[test]
+    fn buffered_bytes_should_be_written_once_flushed() {
+        let path = env::temp_dir().join("snatch_test_write.bin");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let out_file = OutputFileWriter::new(file).with_write_buffer(4);
+        out_file.set_len(8).unwrap();
+        let mut chunk_writer = out_file.get_chunk_writer(2);
+
+        chunk_writer.write(0, b"ab").unwrap();
+        chunk_writer.write(2, b"c").unwrap();
+        assert_eq!(3, chunk_writer.pending());
+        // Not contiguous, so the buffered bytes are written first
+        chunk_writer.write(4, b"e").unwrap();
+        assert_eq!(1, chunk_writer.pending());
+        // Larger than the buffer, so written at once
+        chunk_writer.write(0, b"ABCDEF").unwrap();
+        assert_eq!(0, chunk_writer.pending());
+        chunk_writer.write(3, b"d").unwrap();
+        chunk_writer.flush().unwrap();
+        assert_eq!(0, chunk_writer.pending());
+
+        let mut content = [0; 8];
+        out_file.read(0, &mut content).unwrap();
+        assert_eq!(b"\0\0ABCdEF", &content);
+        fs::remove_file(&path).unwrap();
+    }
+
+}